@@ -0,0 +1,147 @@
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Cidr `struct` describes a network of client addresses, such as
+/// `10.1.0.0/16` or `fd00::/8`. A plain IP address describes a network with
+/// only that address.
+///
+/// IPv4-mapped IPv6 addresses, as seen by dual-stack listeners, are matched
+/// like the IPv4 addresses they map.
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::IpAddr;
+/// use tftpd::Cidr;
+///
+/// let network: Cidr = "10.1.0.0/16".parse().unwrap();
+///
+/// assert!(network.contains(&"10.1.2.3".parse::<IpAddr>().unwrap()));
+/// assert!(network.contains(&"::ffff:10.1.2.3".parse::<IpAddr>().unwrap()));
+/// assert!(!network.contains(&"10.2.0.1".parse::<IpAddr>().unwrap()));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Creates a new [`Cidr`] from a network address and a prefix length.
+    /// Host bits of the address are cleared.
+    pub fn new(address: IpAddr, prefix: u8) -> Result<Cidr, Box<dyn Error>> {
+        let (address, prefix) = match address {
+            IpAddr::V6(v6) if v6.to_ipv4_mapped().is_some() && prefix >= 96 => {
+                (address.to_canonical(), prefix - 96)
+            }
+            address => (address, prefix),
+        };
+        let bits = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > bits {
+            return Err(format!("Invalid prefix length {prefix} for {address}").into());
+        }
+
+        Ok(Cidr {
+            address: mask(address, prefix),
+            prefix,
+        })
+    }
+
+    /// Returns whether `address` is part of the network.
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        let address = address.to_canonical();
+        address.is_ipv4() == self.address.is_ipv4() && mask(address, self.prefix) == self.address
+    }
+
+    /// Returns whether the two networks share any address.
+    pub fn overlaps(&self, other: &Cidr) -> bool {
+        self.contains(&other.address) || other.contains(&self.address)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Box<dyn Error>;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('/') {
+            Some((address, prefix)) => Cidr::new(address.parse()?, prefix.parse()?),
+            None => {
+                let address = value.parse::<IpAddr>()?;
+                let prefix = if address.is_ipv4() { 32 } else { 128 };
+                Cidr::new(address, prefix)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+fn mask(address: IpAddr, prefix: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(address) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            IpAddr::V4((u32::from(address) & mask).into())
+        }
+        IpAddr::V6(address) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            IpAddr::V6((u128::from(address) & mask).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_addresses() {
+        let cases = [
+            ("10.1.0.0/16", "10.1.255.1", true),
+            ("10.1.0.0/16", "10.2.0.1", false),
+            ("10.1.2.3/16", "10.1.0.1", true),
+            ("0.0.0.0/0", "192.168.1.1", true),
+            ("0.0.0.0/0", "::1", false),
+            ("192.168.1.7", "192.168.1.7", true),
+            ("192.168.1.7", "192.168.1.8", false),
+            ("10.0.0.0/8", "::ffff:10.0.0.1", true),
+            ("::ffff:10.0.0.0/104", "10.1.0.1", true),
+            ("fd00::/8", "fd12::1", true),
+            ("fd00::/8", "fe80::1", false),
+            ("::/0", "::1", true),
+        ];
+
+        for (cidr, address, expected) in cases {
+            let cidr: Cidr = cidr.parse().unwrap();
+            assert_eq!(
+                cidr.contains(&address.parse().unwrap()),
+                expected,
+                "{cidr} contains {address}"
+            );
+        }
+    }
+
+    #[test]
+    fn detects_overlapping_networks() {
+        let network: Cidr = "10.0.0.0/8".parse().unwrap();
+
+        assert!(network.overlaps(&"10.1.0.0/16".parse().unwrap()));
+        assert!("10.1.0.0/16".parse::<Cidr>().unwrap().overlaps(&network));
+        assert!(!network.overlaps(&"11.0.0.0/8".parse().unwrap()));
+    }
+
+    #[test]
+    fn returns_error_on_invalid_cidr() {
+        for cidr in ["10.0.0.0/33", "10.0.0/8", "fd00::/129", "10.0.0.0/", "host"] {
+            assert!(cidr.parse::<Cidr>().is_err(), "{cidr}");
+        }
+    }
+}
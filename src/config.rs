@@ -1,13 +1,28 @@
+use crate::policy::Policy;
+use crate::toml::{self, Entry, Table, Value};
+use crate::{json, sys, Cidr};
 use std::error::Error;
-use std::net::Ipv4Addr;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 use std::{env, process};
 
+const MAX_BLOCK_SIZE: usize = 65464;
+const DEFAULT_MAX_RETRIES: u32 = 6;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(60);
+const DEFAULT_DIR_MODE: u32 = 0o755;
+
 /// Configuration `struct` used for parsing TFTP options from user
 /// input.
 ///
-/// This `struct` is meant to be created by [`Config::new()`]. See its
-/// documentation for more.
+/// This `struct` is meant to be created by [`Config::new()`] or
+/// [`Config::from_file()`]. See their documentation for more.
 ///
 /// # Example
 ///
@@ -18,38 +33,809 @@ use std::{env, process};
 ///
 /// let config = Config::new(env::args()).unwrap();
 /// ```
+#[derive(Clone, Debug, PartialEq)]
 pub struct Config {
     /// Local IP address of the TFTP Server. (default: 127.0.0.1)
-    pub ip_address: Ipv4Addr,
+    pub ip_address: IpAddr,
     /// Local Port number of the TFTP Server. (default: 69)
     pub port: u16,
     /// Default directory of the TFTP Server. (default: current working directory)
     pub directory: PathBuf,
+    /// Listen on the IPv6 unspecified address `::` and accept IPv4 clients
+    /// as well, ignoring [`Config::ip_address`]. (default: false)
+    pub dual_stack: bool,
     /// Use a single port for both sending and receiving. (default: false)
     pub single_port: bool,
+    /// Range of local ports transfer sockets are bound to. (default: any port)
+    pub port_range: Option<RangeInclusive<u16>>,
     /// Refuse all write requests, making the server read-only. (default: false)
     pub read_only: bool,
+    /// Refuse all read requests, making the server a drop box for uploads.
+    /// (default: false)
+    pub write_only: bool,
+    /// Symbolic links followed when resolving requested files. (default:
+    /// links inside of the served directory)
+    pub symlinks: SymlinkPolicy,
+    /// Leading path prefix removed from requested file names, such as
+    /// `/tftpboot`, for clients that request absolute paths. (default: none)
+    pub strip_prefix: Option<String>,
+    /// Match requested file names to existing files ignoring ASCII case, for
+    /// clients that change the case of file names. (default: false)
+    pub case_insensitive: bool,
+    /// Clients allowed to read files. (default: all clients)
+    pub read_access: AccessList,
+    /// Clients allowed to write files. (default: all clients)
+    pub write_access: AccessList,
+    /// Drop requests denied by [`Config::read_access`] or
+    /// [`Config::write_access`] silently instead of answering with an access
+    /// violation. (default: false)
+    pub drop_denied: bool,
+    /// Files clients are allowed to read. (default: all files)
+    pub read_files: FileFilter,
+    /// Files clients are allowed to write. (default: all files)
+    pub write_files: FileFilter,
+    /// Whether uploads may replace existing files. (default: never)
+    pub overwrite: OverwritePolicy,
+    /// Create the missing parent directories of uploaded files inside of
+    /// the served directory. (default: false)
+    pub create_dirs: bool,
+    /// Permission bits of the directories created for uploads. (default:
+    /// 0755)
+    pub dir_mode: u32,
+    /// Permission bits of uploaded files. (default: given by the umask)
+    pub file_mode: Option<u32>,
+    /// User, by name or id, that owns uploaded files and the directories
+    /// created for them. (default: user of the process)
+    pub file_owner: Option<String>,
+    /// Group, by name or id, that owns uploaded files and the directories
+    /// created for them. (default: group of the process)
+    pub file_group: Option<String>,
+    /// Limits for the size of uploads and the space they leave free.
+    pub quotas: Quotas,
+    /// Limits for the rate of requests from clients.
+    pub rate_limits: RateLimits,
+    /// Upper bounds for the options negotiated with clients.
+    pub limits: OptionLimits,
+    /// Timeout and number of retries of transfers.
+    pub retry: RetryPolicy,
+    /// Use the sockets passed by systemd socket activation instead of binding
+    /// [`Config::ip_address`] and [`Config::port`]. (default: false)
+    pub socket_activation: bool,
+    /// Use the socket passed by inetd as the standard input in `wait` mode
+    /// instead of binding [`Config::ip_address`] and [`Config::port`].
+    /// (default: false)
+    pub inetd: bool,
+    /// Stop listening once no requests have arrived and no transfers have
+    /// been running for this long. (default: never, or 900 seconds in inetd
+    /// mode)
+    pub idle_timeout: Option<Duration>,
+    /// User to switch to after binding the sockets. (default: none)
+    pub user: Option<String>,
+    /// Group to switch to after binding the sockets. (default: primary group
+    /// of [`Config::user`])
+    pub group: Option<String>,
+    /// Confine the process to [`Config::directory`] after binding the
+    /// sockets. (default: false)
+    pub chroot: bool,
+    /// Addresses to listen on instead of [`Config::ip_address`] and
+    /// [`Config::port`], each optionally with its own directory and
+    /// read-only and write-only settings. (default: empty)
+    pub listeners: Vec<Listener>,
+    /// Settings for clients selected by their address. The first profile
+    /// matching a client is used, and clients without a matching profile use
+    /// the settings of the listener. (default: empty)
+    pub profiles: Vec<Profile>,
+    /// Request path prefixes served from their own directories. The mount
+    /// with the longest matching prefix is used. (default: empty)
+    pub mounts: Vec<Mount>,
 }
 
-impl Config {
-    /// Creates a new configuration by parsing the supplied arguments. It is
-    /// intended for use with [`env::args()`].
-    pub fn new<T: Iterator<Item = String>>(mut args: T) -> Result<Config, Box<dyn Error>> {
-        let mut config = Config {
-            ip_address: Ipv4Addr::new(127, 0, 0, 1),
+/// Listener `struct` describes an address the server listens on. Listeners
+/// without their own directory, read-only or write-only setting use the ones
+/// from [`Config`].
+///
+/// Listeners can be parsed from strings in the form
+/// `ADDRESS:PORT[,DIRECTORY][,read-only|write-only|read-write]`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::{net::SocketAddr, path::PathBuf};
+/// use tftpd::Listener;
+///
+/// let listener: Listener = "[::1]:6969,/,read-only".parse().unwrap();
+///
+/// assert_eq!(listener.address, "[::1]:6969".parse::<SocketAddr>().unwrap());
+/// assert_eq!(listener.directory, Some(PathBuf::from("/")));
+/// assert_eq!(listener.read_only, Some(true));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Listener {
+    /// Local address of the listener.
+    pub address: SocketAddr,
+    /// Directory served by the listener. (default: [`Config::directory`])
+    pub directory: Option<PathBuf>,
+    /// Refuse all write requests on the listener. (default: [`Config::read_only`])
+    pub read_only: Option<bool>,
+    /// Refuse all read requests on the listener. (default: [`Config::write_only`])
+    pub write_only: Option<bool>,
+}
+
+impl Listener {
+    /// Creates a new [`Listener`] on the supplied address, using the directory
+    /// and read-only and write-only settings from [`Config`].
+    pub fn new(address: SocketAddr) -> Listener {
+        Listener {
+            address,
+            directory: None,
+            read_only: None,
+            write_only: None,
+        }
+    }
+}
+
+impl FromStr for Listener {
+    type Err = Box<dyn Error>;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(',');
+        let mut listener = Listener::new(parts.next().unwrap_or_default().parse()?);
+
+        for part in parts {
+            match part {
+                "read-only" => listener.read_only = Some(true),
+                "write-only" => listener.write_only = Some(true),
+                "read-write" => {
+                    listener.read_only = Some(false);
+                    listener.write_only = Some(false);
+                }
+                directory if listener.directory.is_none() => {
+                    if !directory_exists(Path::new(directory)) {
+                        return Err(format!("{directory} does not exist").into());
+                    }
+                    listener.directory = Some(PathBuf::from(directory));
+                }
+                invalid => return Err(format!("Invalid listener setting: {invalid}").into()),
+            }
+        }
+
+        Ok(listener)
+    }
+}
+
+/// Mount `struct` maps the requests for files below a path prefix to a
+/// separate directory. A mount can be read-only or write-only on top of the
+/// read-only setting of the request, and sets the modes and owner of the
+/// files uploaded below it over those of the [`Profile`] and [`Config`].
+///
+/// Mounts can be parsed from strings in the form
+/// `PREFIX=DIRECTORY[,read-only|write-only][,file-mode=MODE][,dir-mode=MODE][,owner=USER][,group=GROUP]`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::path::PathBuf;
+/// use tftpd::Mount;
+///
+/// let mount: Mount = "boot/=/,read-only".parse().unwrap();
+///
+/// assert_eq!(mount.prefix, "boot");
+/// assert_eq!(mount.directory, PathBuf::from("/"));
+/// assert!(mount.read_only);
+/// assert!(mount.matches("boot/kernel"));
+/// assert!(!mount.matches("bootloader"));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mount {
+    /// Path prefix of the requests the mount applies to, without leading or
+    /// trailing slashes.
+    pub prefix: String,
+    /// Directory the files below the prefix are found in.
+    pub directory: PathBuf,
+    /// Refuse all write requests below the prefix. (default: false)
+    pub read_only: bool,
+    /// Refuse all read requests below the prefix. (default: false)
+    pub write_only: bool,
+    /// Permission bits of files uploaded below the prefix. (default: none)
+    pub file_mode: Option<u32>,
+    /// Permission bits of directories created below the prefix. (default:
+    /// none)
+    pub dir_mode: Option<u32>,
+    /// Owner of files uploaded below the prefix. (default: none)
+    pub file_owner: Option<String>,
+    /// Group of files uploaded below the prefix. (default: none)
+    pub file_group: Option<String>,
+}
+
+impl Mount {
+    /// Creates a new [`Mount`] of `directory` at `prefix`, without
+    /// restrictions of its own.
+    pub fn new<S: AsRef<str>, P: Into<PathBuf>>(prefix: S, directory: P) -> Mount {
+        Mount {
+            prefix: prefix.as_ref().trim_matches('/').to_string(),
+            directory: directory.into(),
+            read_only: false,
+            write_only: false,
+            file_mode: None,
+            dir_mode: None,
+            file_owner: None,
+            file_group: None,
+        }
+    }
+
+    /// Returns whether `filename` is below the prefix of the mount.
+    pub fn matches(&self, filename: &str) -> bool {
+        self.strip_prefix(filename, false).is_some()
+    }
+
+    /// Returns the part of `filename` below the prefix of the mount,
+    /// optionally ignoring ASCII case.
+    pub(crate) fn strip_prefix<'a>(&self, filename: &'a str, ignore_case: bool) -> Option<&'a str> {
+        let filename = filename.trim_start_matches('/');
+        let head = filename.get(..self.prefix.len())?;
+        if !(head == self.prefix || ignore_case && head.eq_ignore_ascii_case(&self.prefix)) {
+            return None;
+        }
+        let rest = &filename[self.prefix.len()..];
+
+        match rest.strip_prefix('/') {
+            Some(rest) => Some(rest),
+            None if rest.is_empty() => Some(rest),
+            None => None,
+        }
+    }
+}
+
+impl FromStr for Mount {
+    type Err = Box<dyn Error>;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (prefix, settings) = value
+            .split_once('=')
+            .ok_or("Mount must be in the form PREFIX=DIRECTORY")?;
+        let mut settings = settings.split(',');
+        let directory = settings.next().unwrap_or_default();
+        if !directory_exists(Path::new(directory)) {
+            return Err(format!("{directory} does not exist").into());
+        }
+        let mut mount = Mount::new(prefix, directory);
+
+        for setting in settings {
+            match setting.split_once('=') {
+                None if setting == "read-only" => mount.read_only = true,
+                None if setting == "write-only" => mount.write_only = true,
+                Some(("file-mode", mode)) => mount.file_mode = Some(parse_mode(mode)?),
+                Some(("dir-mode", mode)) => mount.dir_mode = Some(parse_mode(mode)?),
+                Some(("owner", owner)) => mount.file_owner = Some(owner.to_string()),
+                Some(("group", group)) => mount.file_group = Some(group.to_string()),
+                _ => return Err(format!("Invalid mount setting: {setting}").into()),
+            }
+        }
+
+        Ok(mount)
+    }
+}
+
+/// OptionLimits `struct` holds the upper bounds the server accepts for the
+/// transfer options requested by clients. Requested values above a limit are
+/// lowered to the limit in the option acknowledgement.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::OptionLimits;
+///
+/// let limits = OptionLimits {
+///     max_block_size: 1468,
+///     ..Default::default()
+/// };
+/// assert_eq!(limits.max_window_size, u16::MAX);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OptionLimits {
+    /// Largest block size the server agrees to. (default: 65464)
+    pub max_block_size: usize,
+    /// Largest window size the server agrees to. (default: 65535)
+    pub max_window_size: u16,
+}
+
+/// RetryPolicy `struct` decides how long the server waits for a client, and
+/// how many times it does so before abandoning a transfer.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use tftpd::RetryPolicy;
+///
+/// let retry = RetryPolicy {
+///     max_retries: 10,
+///     ..Default::default()
+/// };
+/// assert_eq!(retry.timeout, Duration::from_secs(5));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of timeouts after which a transfer is abandoned. (default: 6)
+    pub max_retries: u32,
+    /// Time to wait for a client that has not negotiated a timeout.
+    /// (default: 5 seconds)
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: DEFAULT_MAX_RETRIES,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+/// RateLimit `struct` describes a token bucket that refills with `rate`
+/// requests per second and holds up to `burst` requests.
+///
+/// Rate limits can be parsed from strings in the form `RATE[/BURST]`, where
+/// the burst defaults to the rate.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::RateLimit;
+///
+/// let limit: RateLimit = "5/20".parse().unwrap();
+///
+/// assert_eq!(limit, RateLimit { rate: 5, burst: 20 });
+/// assert_eq!("5".parse::<RateLimit>().unwrap().burst, 5);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Requests per second the bucket refills with.
+    pub rate: u32,
+    /// Requests the bucket holds when full.
+    pub burst: u32,
+}
+
+impl FromStr for RateLimit {
+    type Err = Box<dyn Error>;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (rate, burst) = match value.split_once('/') {
+            Some((rate, burst)) => (rate.parse()?, burst.parse()?),
+            None => (value.parse()?, value.parse()?),
+        };
+
+        Ok(RateLimit { rate, burst })
+    }
+}
+
+impl Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.rate, self.burst)
+    }
+}
+
+/// RateLimits `struct` protects the server from clients flooding it with
+/// requests. Requests over a limit are dropped before any file is looked
+/// up, and clients that keep exceeding their limit are banned for a while.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::RateLimits;
+///
+/// let limits = RateLimits {
+///     client: Some("2/10".parse().unwrap()),
+///     ban_after: Some(50),
+///     ..Default::default()
+/// };
+/// assert!(limits.global.is_none());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimits {
+    /// Limit for the requests of each client address. (default: none)
+    pub client: Option<RateLimit>,
+    /// Limit for the requests of all clients together. (default: none)
+    pub global: Option<RateLimit>,
+    /// Ban a client after this many of its requests in a row exceeded its
+    /// limit. (default: never)
+    pub ban_after: Option<u32>,
+    /// Time requests from a banned client are dropped for. (default: 60
+    /// seconds)
+    pub ban_duration: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            client: None,
+            global: None,
+            ban_after: None,
+            ban_duration: DEFAULT_BAN_DURATION,
+        }
+    }
+}
+
+/// Quotas `struct` limits how much clients may upload. An upload that
+/// crosses a limit is stopped with a disk full error and its partial file
+/// is removed.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::Quotas;
+///
+/// let quotas = Quotas {
+///     max_file_size: Some(64 * 1024 * 1024),
+///     min_free_space: Some(1024 * 1024 * 1024),
+///     ..Default::default()
+/// };
+/// assert!(quotas.client_daily_bytes.is_none());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quotas {
+    /// Largest file a client may upload, in bytes. (default: no limit)
+    pub max_file_size: Option<u64>,
+    /// Bytes each client address may upload per day, counted since midnight
+    /// UTC. (default: no limit)
+    pub client_daily_bytes: Option<u64>,
+    /// Bytes that must stay free on the file system uploads are written to.
+    /// (default: none)
+    pub min_free_space: Option<u64>,
+}
+
+/// SymlinkPolicy `enum` decides which symbolic links are followed when
+/// resolving a requested file.
+///
+/// Symlink policies can be parsed from the strings `deny`, `inside` and
+/// `any`.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::SymlinkPolicy;
+///
+/// let symlinks: SymlinkPolicy = "deny".parse().unwrap();
+///
+/// assert_eq!(symlinks, SymlinkPolicy::Deny);
+/// assert_eq!(SymlinkPolicy::default(), SymlinkPolicy::Inside);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Refuse every request whose path contains a symbolic link.
+    Deny,
+    /// Follow symbolic links as long as they resolve to a path inside of the
+    /// served directory.
+    #[default]
+    Inside,
+    /// Follow all symbolic links, even to paths outside of the served
+    /// directory.
+    Any,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = Box<dyn Error>;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "deny" => Ok(SymlinkPolicy::Deny),
+            "inside" => Ok(SymlinkPolicy::Inside),
+            "any" => Ok(SymlinkPolicy::Any),
+            invalid => Err(format!("Invalid symlink policy: {invalid}").into()),
+        }
+    }
+}
+
+impl Display for SymlinkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymlinkPolicy::Deny => write!(f, "deny"),
+            SymlinkPolicy::Inside => write!(f, "inside"),
+            SymlinkPolicy::Any => write!(f, "any"),
+        }
+    }
+}
+
+/// OverwritePolicy `enum` decides whether a write request may replace a
+/// file that already exists.
+///
+/// Overwrite policies can be parsed from the strings `never`, `always`,
+/// `older-than:MINUTES` and `keep-versions:COUNT`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use tftpd::OverwritePolicy;
+///
+/// let overwrite: OverwritePolicy = "older-than:10".parse().unwrap();
+///
+/// assert_eq!(overwrite, OverwritePolicy::OlderThan(Duration::from_secs(600)));
+/// assert_eq!(OverwritePolicy::default(), OverwritePolicy::Never);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Refuse every write request for an existing file.
+    #[default]
+    Never,
+    /// Replace existing files.
+    Always,
+    /// Replace existing files that were last modified at least this long
+    /// ago.
+    OlderThan(Duration),
+    /// Replace existing files after renaming them to `NAME.TIMESTAMP`,
+    /// keeping at most this many old versions of each file.
+    KeepVersions(usize),
+}
+
+impl FromStr for OverwritePolicy {
+    type Err = Box<dyn Error>;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid overwrite policy: {value}");
+        match value.split_once(':') {
+            None if value == "never" => Ok(OverwritePolicy::Never),
+            None if value == "always" => Ok(OverwritePolicy::Always),
+            Some(("older-than", minutes)) => {
+                let minutes = minutes.parse::<u64>().map_err(|_| invalid())?;
+                Ok(OverwritePolicy::OlderThan(Duration::from_secs(
+                    minutes.saturating_mul(60),
+                )))
+            }
+            Some(("keep-versions", count)) => match count.parse() {
+                Ok(count) if count > 0 => Ok(OverwritePolicy::KeepVersions(count)),
+                _ => Err(invalid().into()),
+            },
+            _ => Err(invalid().into()),
+        }
+    }
+}
+
+impl Display for OverwritePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverwritePolicy::Never => write!(f, "never"),
+            OverwritePolicy::Always => write!(f, "always"),
+            OverwritePolicy::OlderThan(age) => write!(f, "older-than:{}", age.as_secs() / 60),
+            OverwritePolicy::KeepVersions(count) => write!(f, "keep-versions:{count}"),
+        }
+    }
+}
+
+/// AccessList `struct` decides which clients may make a kind of request by
+/// their address. A client is denied if it is in a network of
+/// [`AccessList::deny`], or if [`AccessList::allow`] is not empty and the
+/// client is in none of its networks.
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::IpAddr;
+/// use tftpd::AccessList;
+///
+/// let access = AccessList {
+///     allow: vec!["10.0.0.0/8".parse().unwrap()],
+///     deny: vec!["10.9.0.0/16".parse().unwrap()],
+/// };
+///
+/// assert!(access.allows(&"10.1.0.1".parse::<IpAddr>().unwrap()));
+/// assert!(!access.allows(&"10.9.0.1".parse::<IpAddr>().unwrap()));
+/// assert!(!access.allows(&"192.168.1.1".parse::<IpAddr>().unwrap()));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccessList {
+    /// Networks of the only clients allowed, unless empty. (default: empty)
+    pub allow: Vec<Cidr>,
+    /// Networks of the clients denied. (default: empty)
+    pub deny: Vec<Cidr>,
+}
+
+impl AccessList {
+    /// Returns whether `client` is allowed by the access list.
+    pub fn allows(&self, client: &IpAddr) -> bool {
+        self.denied_by(client).is_none()
+    }
+
+    /// Returns the rule that denies `client`, if any.
+    pub(crate) fn denied_by(&self, client: &IpAddr) -> Option<String> {
+        if let Some(cidr) = self.deny.iter().find(|cidr| cidr.contains(client)) {
+            return Some(format!("deny {cidr}"));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|cidr| cidr.contains(client)) {
+            return Some("no allow rule".to_string());
+        }
+
+        None
+    }
+}
+
+/// FileFilter `struct` decides which requested files may be read or
+/// written by their name. A file is denied if its name matches a pattern of
+/// [`FileFilter::deny`], or if [`FileFilter::allow`] is not empty and the
+/// name matches none of its patterns.
+///
+/// In patterns, `*` matches any characters except `/`, `**` matches any
+/// characters, `?` matches a single character and `[...]` matches one of a
+/// set of characters. Patterns without a `/` match the file name in any
+/// directory, like `*.key`, and patterns with a `/` match the whole path,
+/// like `dumps/*.core`. Patterns ending in `/`, like `.git/`, match every
+/// file below a directory.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::FileFilter;
+///
+/// // Never serve version control data, dotfiles, keys or editor backups.
+/// let read_files = FileFilter {
+///     deny: [".git/", ".*", "*.key", "*~"].map(String::from).to_vec(),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileFilter {
+    /// Patterns of the only file names allowed, unless empty. (default:
+    /// empty)
+    pub allow: Vec<String>,
+    /// Patterns of the file names denied. (default: empty)
+    pub deny: Vec<String>,
+}
+
+/// Profile `struct` holds the settings for the clients in a set of networks.
+/// Settings that are not set are taken from the [`Listener`] the request
+/// arrived on, or from [`Config`].
+///
+/// # Example
+///
+/// ```rust
+/// use std::path::PathBuf;
+/// use tftpd::{OptionLimits, Profile};
+///
+/// // Let the build farm upload artifacts with large blocks.
+/// let profile = Profile {
+///     directory: Some(PathBuf::from("/srv/artifacts")),
+///     read_only: Some(false),
+///     limits: Some(OptionLimits {
+///         max_block_size: 8192,
+///         ..Default::default()
+///     }),
+///     ..Profile::new(vec!["10.2.0.0/16".parse().unwrap()])
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    /// Networks of the clients the profile applies to.
+    pub clients: Vec<Cidr>,
+    /// Directory served to the clients. (default: directory of the listener)
+    pub directory: Option<PathBuf>,
+    /// Refuse all write requests of the clients. (default: read-only setting
+    /// of the listener)
+    pub read_only: Option<bool>,
+    /// Refuse all read requests of the clients. (default: write-only setting
+    /// of the listener)
+    pub write_only: Option<bool>,
+    /// Upper bounds for the options negotiated with the clients. (default:
+    /// [`Config::limits`])
+    pub limits: Option<OptionLimits>,
+    /// Timeout and number of retries of transfers with the clients. (default:
+    /// [`Config::retry`])
+    pub retry: Option<RetryPolicy>,
+    /// Permission bits of files uploaded by the clients. (default:
+    /// [`Config::file_mode`])
+    pub file_mode: Option<u32>,
+    /// Permission bits of directories created for the clients. (default:
+    /// [`Config::dir_mode`])
+    pub dir_mode: Option<u32>,
+    /// Owner of files uploaded by the clients. (default:
+    /// [`Config::file_owner`])
+    pub file_owner: Option<String>,
+    /// Group of files uploaded by the clients. (default:
+    /// [`Config::file_group`])
+    pub file_group: Option<String>,
+}
+
+impl Profile {
+    /// Creates a new [`Profile`] for the supplied networks, without any
+    /// settings of its own.
+    pub fn new(clients: Vec<Cidr>) -> Profile {
+        Profile {
+            clients,
+            directory: None,
+            read_only: None,
+            write_only: None,
+            limits: None,
+            retry: None,
+            file_mode: None,
+            dir_mode: None,
+            file_owner: None,
+            file_group: None,
+        }
+    }
+}
+
+impl Default for OptionLimits {
+    fn default() -> Self {
+        OptionLimits {
+            max_block_size: MAX_BLOCK_SIZE,
+            max_window_size: u16::MAX,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ip_address: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             port: 69,
             directory: env::current_dir().unwrap_or_else(|_| env::temp_dir()),
+            dual_stack: false,
             single_port: false,
+            port_range: None,
             read_only: false,
-        };
+            write_only: false,
+            symlinks: SymlinkPolicy::default(),
+            strip_prefix: None,
+            case_insensitive: false,
+            read_access: AccessList::default(),
+            write_access: AccessList::default(),
+            drop_denied: false,
+            read_files: FileFilter::default(),
+            write_files: FileFilter::default(),
+            overwrite: OverwritePolicy::default(),
+            create_dirs: false,
+            dir_mode: DEFAULT_DIR_MODE,
+            file_mode: None,
+            file_owner: None,
+            file_group: None,
+            quotas: Quotas::default(),
+            rate_limits: RateLimits::default(),
+            limits: OptionLimits::default(),
+            retry: RetryPolicy::default(),
+            socket_activation: false,
+            inetd: false,
+            idle_timeout: None,
+            user: None,
+            group: None,
+            chroot: false,
+            listeners: Vec::new(),
+            profiles: Vec::new(),
+            mounts: Vec::new(),
+        }
+    }
+}
 
-        args.next();
+impl Config {
+    /// Creates a new configuration by parsing the supplied arguments. It is
+    /// intended for use with [`env::args()`].
+    ///
+    /// If the arguments contain `--config FILE`, the configuration file is
+    /// read first, see [`Config::from_file()`], and the other arguments
+    /// override its values.
+    pub fn new<T: Iterator<Item = String>>(args: T) -> Result<Config, Box<dyn Error>> {
+        let args = args.skip(1).collect::<Vec<_>>();
+        let mut config = match args.iter().position(|arg| arg == "--config") {
+            Some(index) => match args.get(index + 1) {
+                Some(file) => Config::from_file(file)?,
+                None => return Err("Missing config file after flag".into()),
+            },
+            None => Config::default(),
+        };
+        let mut listeners = Vec::new();
+        let mut mounts = Vec::new();
+        let mut read_access = AccessList::default();
+        let mut write_access = AccessList::default();
+        let mut read_files = FileFilter::default();
+        let mut write_files = FileFilter::default();
+        let mut args = args.into_iter();
 
         while let Some(arg) = args.next() {
             match arg.as_str() {
+                "--config" => {
+                    args.next();
+                }
                 "-i" | "--ip-address" => {
                     if let Some(ip_str) = args.next() {
-                        config.ip_address = ip_str.parse::<Ipv4Addr>()?;
+                        config.ip_address = ip_str.parse::<IpAddr>()?;
                     } else {
                         return Err("Missing ip address after flag".into());
                     }
@@ -63,7 +849,7 @@ impl Config {
                 }
                 "-d" | "--directory" => {
                     if let Some(dir_str) = args.next() {
-                        if !Path::new(&dir_str).exists() {
+                        if !directory_exists(Path::new(&dir_str)) {
                             return Err(format!("{dir_str} does not exist").into());
                         }
                         config.directory = PathBuf::from(dir_str);
@@ -71,51 +857,1066 @@ impl Config {
                         return Err("Missing directory after flag".into());
                     }
                 }
+                "-l" | "--listen" => {
+                    if let Some(listener_str) = args.next() {
+                        listeners.push(listener_str.parse()?);
+                    } else {
+                        return Err("Missing listener after flag".into());
+                    }
+                }
+                "-m" | "--mount" => {
+                    if let Some(mount_str) = args.next() {
+                        mounts.push(mount_str.parse()?);
+                    } else {
+                        return Err("Missing mount after flag".into());
+                    }
+                }
+                "-6" | "--dual-stack" => {
+                    config.dual_stack = true;
+                }
                 "-s" | "--single-port" => {
                     config.single_port = true;
                 }
+                "--port-range" => {
+                    if let Some(range_str) = args.next() {
+                        config.port_range = Some(parse_port_range(&range_str)?);
+                    } else {
+                        return Err("Missing port range after flag".into());
+                    }
+                }
                 "-r" | "--read-only" => {
                     config.read_only = true;
                 }
-                "-h" | "--help" => {
-                    println!("TFTP Server Daemon\n");
-                    println!("Usage: tftpd [OPTIONS]\n");
-                    println!("Options:");
-                    println!("  -i, --ip-address <IP ADDRESS>\tSet the ip address of the server (default: 127.0.0.1)");
-                    println!(
-                        "  -p, --port <PORT>\t\tSet the listening port of the server (default: 69)"
-                    );
-                    println!("  -d, --directory <DIRECTORY>\tSet the listening port of the server (default: Current Working Directory)");
-                    println!("  -s, --single-port\t\tUse a single port for both sending and receiving (default: false)");
-                    println!("  -r, --read-only\t\tRefuse all write requests, making the server read-only (default: false)");
-                    println!("  -h, --help\t\t\tPrint help information");
-                    process::exit(0);
+                "-w" | "--write-only" => {
+                    config.write_only = true;
                 }
-                invalid => return Err(format!("Invalid flag: {invalid}").into()),
-            }
-        }
-
-        Ok(config)
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::str::FromStr;
-
-    use super::*;
-
-    #[test]
-    fn parses_full_config() {
-        let config = Config::new(
-            vec!["/", "-i", "0.0.0.0", "-p", "1234", "-d", "/", "-s", "-r"]
-                .iter()
-                .map(|s| s.to_string()),
-        )
-        .unwrap();
-
-        assert_eq!(config.ip_address, Ipv4Addr::new(0, 0, 0, 0));
-        assert_eq!(config.port, 1234);
+                "--symlinks" => {
+                    if let Some(symlinks_str) = args.next() {
+                        config.symlinks = symlinks_str.parse()?;
+                    } else {
+                        return Err("Missing symlink policy after flag".into());
+                    }
+                }
+                "--strip-prefix" => {
+                    if let Some(prefix_str) = args.next() {
+                        config.strip_prefix = Some(prefix_str);
+                    } else {
+                        return Err("Missing prefix after flag".into());
+                    }
+                }
+                "--case-insensitive" => {
+                    config.case_insensitive = true;
+                }
+                "--overwrite" => {
+                    if let Some(overwrite_str) = args.next() {
+                        config.overwrite = overwrite_str.parse()?;
+                    } else {
+                        return Err("Missing overwrite policy after flag".into());
+                    }
+                }
+                "--create-dirs" => {
+                    config.create_dirs = true;
+                }
+                "--dir-mode" => {
+                    if let Some(mode_str) = args.next() {
+                        config.dir_mode = parse_mode(&mode_str)?;
+                    } else {
+                        return Err("Missing mode after flag".into());
+                    }
+                }
+                "--file-mode" => {
+                    if let Some(mode_str) = args.next() {
+                        config.file_mode = Some(parse_mode(&mode_str)?);
+                    } else {
+                        return Err("Missing mode after flag".into());
+                    }
+                }
+                "--file-owner" => {
+                    if let Some(owner_str) = args.next() {
+                        config.file_owner = Some(owner_str);
+                    } else {
+                        return Err("Missing owner after flag".into());
+                    }
+                }
+                "--file-group" => {
+                    if let Some(group_str) = args.next() {
+                        config.file_group = Some(group_str);
+                    } else {
+                        return Err("Missing group after flag".into());
+                    }
+                }
+                "--allow-read" | "--deny-read" | "--allow-write" | "--deny-write" => {
+                    let Some(cidr_str) = args.next() else {
+                        return Err("Missing client network after flag".into());
+                    };
+                    let cidr = cidr_str.parse()?;
+                    match arg.as_str() {
+                        "--allow-read" => read_access.allow.push(cidr),
+                        "--deny-read" => read_access.deny.push(cidr),
+                        "--allow-write" => write_access.allow.push(cidr),
+                        _ => write_access.deny.push(cidr),
+                    }
+                }
+                "--allow-read-file" | "--deny-read-file" | "--allow-write-file"
+                | "--deny-write-file" => {
+                    let Some(pattern) = args.next() else {
+                        return Err("Missing file pattern after flag".into());
+                    };
+                    match arg.as_str() {
+                        "--allow-read-file" => read_files.allow.push(pattern),
+                        "--deny-read-file" => read_files.deny.push(pattern),
+                        "--allow-write-file" => write_files.allow.push(pattern),
+                        _ => write_files.deny.push(pattern),
+                    }
+                }
+                "--drop-denied" => {
+                    config.drop_denied = true;
+                }
+                "--rate-limit" => {
+                    if let Some(limit_str) = args.next() {
+                        config.rate_limits.client = Some(limit_str.parse()?);
+                    } else {
+                        return Err("Missing rate limit after flag".into());
+                    }
+                }
+                "--global-rate-limit" => {
+                    if let Some(limit_str) = args.next() {
+                        config.rate_limits.global = Some(limit_str.parse()?);
+                    } else {
+                        return Err("Missing rate limit after flag".into());
+                    }
+                }
+                "--ban-after" => {
+                    if let Some(count_str) = args.next() {
+                        config.rate_limits.ban_after = Some(count_str.parse()?);
+                    } else {
+                        return Err("Missing request count after flag".into());
+                    }
+                }
+                "--ban-duration" => {
+                    if let Some(duration_str) = args.next() {
+                        config.rate_limits.ban_duration =
+                            Duration::from_secs(duration_str.parse()?);
+                    } else {
+                        return Err("Missing ban duration after flag".into());
+                    }
+                }
+                "--max-file-size" | "--client-daily-quota" | "--min-free-space" => {
+                    let Some(bytes_str) = args.next() else {
+                        return Err("Missing number of bytes after flag".into());
+                    };
+                    let bytes = Some(bytes_str.parse()?);
+                    match arg.as_str() {
+                        "--max-file-size" => config.quotas.max_file_size = bytes,
+                        "--client-daily-quota" => config.quotas.client_daily_bytes = bytes,
+                        _ => config.quotas.min_free_space = bytes,
+                    }
+                }
+                "--socket-activation" => {
+                    config.socket_activation = true;
+                }
+                "--inetd" => {
+                    config.inetd = true;
+                }
+                "--idle-timeout" => {
+                    if let Some(timeout_str) = args.next() {
+                        config.idle_timeout =
+                            Some(Duration::from_secs(timeout_str.parse::<u64>()?));
+                    } else {
+                        return Err("Missing idle timeout after flag".into());
+                    }
+                }
+                "-u" | "--user" => {
+                    if let Some(user_str) = args.next() {
+                        config.user = Some(user_str);
+                    } else {
+                        return Err("Missing user after flag".into());
+                    }
+                }
+                "-g" | "--group" => {
+                    if let Some(group_str) = args.next() {
+                        config.group = Some(group_str);
+                    } else {
+                        return Err("Missing group after flag".into());
+                    }
+                }
+                "-c" | "--chroot" => {
+                    config.chroot = true;
+                }
+                "-h" | "--help" => {
+                    println!("TFTP Server Daemon\n");
+                    println!("Usage: tftpd [OPTIONS]");
+                    println!("       tftpd check-config [--format toml|json] [OPTIONS]\n");
+                    println!("Options:");
+                    println!("      --config <FILE>\t\tRead the configuration from a TOML file, overridden by other flags\n\t\t\t\tOnly TOML strings, integers, booleans and arrays are supported");
+                    println!("  -i, --ip-address <IP ADDRESS>\tSet the ip address of the server (default: 127.0.0.1)");
+                    println!(
+                        "  -p, --port <PORT>\t\tSet the listening port of the server (default: 69)"
+                    );
+                    println!("  -d, --directory <DIRECTORY>\tSet the listening port of the server (default: Current Working Directory)");
+                    println!("  -l, --listen <ADDRESS:PORT>[,DIRECTORY][,read-only|write-only]\n\t\t\t\tListen on an address instead of the ip address and port, can be repeated");
+                    println!("  -m, --mount <PREFIX=DIRECTORY>[,read-only|write-only][,file-mode=MODE][,dir-mode=MODE][,owner=USER][,group=GROUP]\n\t\t\t\tServe requests below a path prefix from a directory, can be repeated");
+                    println!("  -6, --dual-stack\t\tListen on :: for both IPv6 and IPv4 clients (default: false)");
+                    println!("  -s, --single-port\t\tUse a single port for both sending and receiving (default: false)");
+                    println!("      --port-range <START:END>\tBind transfer sockets to ports in this range (default: any port)");
+                    println!("  -r, --read-only\t\tRefuse all write requests, making the server read-only (default: false)");
+                    println!("  -w, --write-only\t\tRefuse all read requests, making the server a drop box (default: false)");
+                    println!("      --symlinks <deny|inside|any>\tFollow no symlinks, only ones inside the directory or any (default: inside)");
+                    println!("      --strip-prefix <PREFIX>\tRemove a leading prefix such as /tftpboot from requested file names (default: none)");
+                    println!("      --case-insensitive\tMatch requested file names to files ignoring case (default: false)");
+                    println!("      --allow-read <CIDR>\t\tOnly allow reads from these networks, can be repeated (default: all)");
+                    println!("      --deny-read <CIDR>\t\tDeny reads from a network, can be repeated (default: none)");
+                    println!("      --allow-write <CIDR>\tOnly allow writes from these networks, can be repeated (default: all)");
+                    println!("      --deny-write <CIDR>\t\tDeny writes from a network, can be repeated (default: none)");
+                    println!("      --drop-denied\t\tDrop denied requests silently instead of answering (default: false)");
+                    println!("      --allow-read-file <PATTERN>\tOnly allow reading files matching these patterns, can be repeated (default: all)");
+                    println!("      --deny-read-file <PATTERN>\tDeny reading files matching a pattern, can be repeated (default: none)");
+                    println!("      --allow-write-file <PATTERN>\tOnly allow writing files matching these patterns, can be repeated (default: all)");
+                    println!("      --deny-write-file <PATTERN>\tDeny writing files matching a pattern, can be repeated (default: none)");
+                    println!("      --overwrite <POLICY>\tReplace existing files never, always, older-than:MINUTES or keep-versions:COUNT (default: never)");
+                    println!("      --create-dirs\t\tCreate missing parent directories of uploaded files (default: false)");
+                    println!("      --dir-mode <MODE>\t\tSet the octal mode of created directories (default: 0755)");
+                    println!("      --file-mode <MODE>\t\tSet the octal mode of uploaded files (default: umask)");
+                    println!("      --file-owner <USER>\tSet the owner of uploaded files and created directories (default: none)");
+                    println!("      --file-group <GROUP>\tSet the group of uploaded files and created directories (default: none)");
+                    println!("      --rate-limit <RATE[/BURST]>\tLimit the requests per second of each client (default: none)");
+                    println!("      --global-rate-limit <RATE[/BURST]>\n\t\t\t\tLimit the requests per second of all clients (default: none)");
+                    println!("      --ban-after <COUNT>\t\tBan clients after this many requests over their limit in a row (default: never)");
+                    println!("      --ban-duration <SECONDS>\tDrop requests of banned clients for this long (default: 60)");
+                    println!("      --max-file-size <BYTES>\tRefuse uploads larger than this (default: no limit)");
+                    println!("      --client-daily-quota <BYTES>\tLimit the bytes each client may upload per day (default: no limit)");
+                    println!("      --min-free-space <BYTES>\tStop uploads that would leave less free space than this (default: none)");
+                    println!("      --socket-activation\tUse the sockets passed by systemd socket activation (default: false)");
+                    println!("      --inetd\t\t\tUse the socket passed by inetd in wait mode as standard input (default: false)");
+                    println!("      --idle-timeout <SECONDS>\tExit after being idle for this many seconds (default: never, 900 with --inetd)");
+                    println!("  -u, --user <USER>\t\tSwitch to this user after binding the sockets (default: none)");
+                    println!("  -g, --group <GROUP>\t\tSwitch to this group after binding the sockets (default: primary group of the user)");
+                    println!("  -c, --chroot\t\t\tConfine the process to the directory after binding the sockets (default: false)");
+                    println!("  -h, --help\t\t\tPrint help information");
+                    process::exit(0);
+                }
+                invalid => return Err(format!("Invalid flag: {invalid}").into()),
+            }
+        }
+
+        // Listeners, mounts, access lists and file filters on the command
+        // line replace the ones from the file instead of adding to them.
+        if !listeners.is_empty() {
+            config.listeners = listeners;
+        }
+        if !mounts.is_empty() {
+            config.mounts = mounts;
+        }
+        for (list, cli) in [
+            (&mut config.read_access.allow, read_access.allow),
+            (&mut config.read_access.deny, read_access.deny),
+            (&mut config.write_access.allow, write_access.allow),
+            (&mut config.write_access.deny, write_access.deny),
+        ] {
+            if !cli.is_empty() {
+                *list = cli;
+            }
+        }
+        for (list, cli) in [
+            (&mut config.read_files.allow, read_files.allow),
+            (&mut config.read_files.deny, read_files.deny),
+            (&mut config.write_files.allow, write_files.allow),
+            (&mut config.write_files.deny, write_files.deny),
+        ] {
+            if !cli.is_empty() {
+                *list = cli;
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Creates a new configuration by reading a TOML configuration file.
+    /// Top level keys are named like the fields of [`Config`], while option
+    /// limits, the retry policy, access lists, file filters, rate limits and
+    /// quotas are set in the `[limits]`, `[retry]`, `[read_access]`,
+    /// `[write_access]`, `[read_files]`, `[write_files]`, `[rate_limits]`
+    /// and `[quotas]` tables,
+    /// and every `[[listener]]`, `[[profile]]` and `[[mount]]` table adds a
+    /// [`Listener`], [`Profile`] or [`Mount`]. Unknown keys are rejected.
+    ///
+    /// Only the subset of TOML the settings need is read: strings, integers,
+    /// booleans and arrays of those, which may span lines and end with a
+    /// comma. Inline tables, dotted keys, floats and dates are not supported.
+    ///
+    /// After the server confined itself to its directory with `--chroot`, the
+    /// file and the directories it names are looked up inside of it, so a
+    /// file outside of the directory can no longer be read.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// directory = "/srv/tftp"
+    /// read_only = true
+    /// port_range = "50000:50100"
+    ///
+    /// [limits]
+    /// max_block_size = 1468
+    ///
+    /// [[listener]]
+    /// address = "[::]:69"
+    ///
+    /// [[profile]]
+    /// clients = ["10.2.0.0/16"]
+    /// directory = "/srv/artifacts"
+    /// read_only = false
+    /// max_block_size = 8192
+    ///
+    /// [[mount]]
+    /// prefix = "boot"
+    /// directory = "/srv/tftp/boot"
+    /// read_only = true
+    /// ```
+    pub fn from_file<P: AsRef<Path>>(file: P) -> Result<Config, Box<dyn Error>> {
+        let file = file.as_ref();
+        let text = sys::path_in_root(file)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "outside of the chroot"))
+            .and_then(fs::read_to_string)
+            .map_err(|err| format!("Could not read {}: {err}", file.display()))?;
+
+        Config::from_toml(&text).map_err(|err| format!("{}: {err}", file.display()).into())
+    }
+
+    /// Returns the configuration in the TOML format read by
+    /// [`Config::from_file()`], with every setting written out.
+    pub fn to_toml(&self) -> String {
+        toml::write(&self.to_tables())
+    }
+
+    /// Returns the configuration as a JSON object, with the same structure
+    /// as [`Config::to_toml()`].
+    pub fn to_json(&self) -> String {
+        json::write(&self.to_tables())
+    }
+
+    /// Checks the configuration without binding any sockets, and returns
+    /// every problem found. Besides the settings themselves, this checks
+    /// that directories can be read and written as configured by the user
+    /// running the check, that no mounts share or nest directories, and that no
+    /// profiles share client networks.
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let mut directories = vec![(self.directory.clone(), self.read_only, self.write_only)];
+        for listener in &self.listeners {
+            if let Some(directory) = &listener.directory {
+                directories.push((
+                    directory.clone(),
+                    listener.read_only.unwrap_or(self.read_only),
+                    listener.write_only.unwrap_or(self.write_only),
+                ));
+            }
+        }
+        for profile in &self.profiles {
+            if let Some(directory) = &profile.directory {
+                directories.push((
+                    directory.clone(),
+                    profile.read_only.unwrap_or(self.read_only),
+                    profile.write_only.unwrap_or(self.write_only),
+                ));
+            }
+        }
+        for mount in &self.mounts {
+            directories.push((
+                mount.directory.clone(),
+                mount.read_only || self.read_only,
+                mount.write_only || self.write_only,
+            ));
+        }
+        for (directory, read_only, write_only) in directories {
+            if !directory.is_dir() {
+                problems.push(format!("{} is not a directory", directory.display()));
+            } else if let Err(err) = sys::check_access(&directory, !write_only, !read_only) {
+                problems.push(format!("{} cannot be used: {err}", directory.display()));
+            }
+        }
+
+        if let Err(err) = Policy::new(self, 0) {
+            let err = err.to_string();
+            if !problems.contains(&err) {
+                problems.push(err);
+            }
+        }
+
+        for (index, mount) in self.mounts.iter().enumerate() {
+            let directory = mount
+                .directory
+                .canonicalize()
+                .unwrap_or(mount.directory.clone());
+            for other in &self.mounts[index + 1..] {
+                let other_directory = other
+                    .directory
+                    .canonicalize()
+                    .unwrap_or(other.directory.clone());
+                // The overlap is the deeper of the two directories.
+                let overlap = if directory.starts_with(&other_directory) {
+                    Some(&directory)
+                } else if other_directory.starts_with(&directory) {
+                    Some(&other_directory)
+                } else {
+                    None
+                };
+                if let Some(overlap) = overlap {
+                    problems.push(format!(
+                        "Mounts {} and {} overlap in {}",
+                        mount.prefix,
+                        other.prefix,
+                        overlap.display()
+                    ));
+                }
+            }
+        }
+
+        for (index, profile) in self.profiles.iter().enumerate() {
+            for (other_index, other) in self.profiles.iter().enumerate().skip(index + 1) {
+                for cidr in &profile.clients {
+                    for other_cidr in other.clients.iter().filter(|other| cidr.overlaps(other)) {
+                        problems.push(format!(
+                            "Client network {other_cidr} of profile {} overlaps {cidr} of profile {}",
+                            other_index + 1,
+                            index + 1
+                        ));
+                    }
+                }
+            }
+        }
+
+        problems
+    }
+
+    fn to_tables(&self) -> Vec<Table> {
+        let mut root = Table::new("", false);
+        let entries = &mut root.entries;
+        entries.push(Entry::new("ip_address", self.ip_address.to_string()));
+        entries.push(Entry::new("port", i64::from(self.port)));
+        entries.push(Entry::new("directory", path_value(&self.directory)));
+        entries.push(Entry::new("dual_stack", self.dual_stack));
+        entries.push(Entry::new("single_port", self.single_port));
+        if let Some(port_range) = &self.port_range {
+            entries.push(Entry::new(
+                "port_range",
+                format!("{}:{}", port_range.start(), port_range.end()),
+            ));
+        }
+        entries.push(Entry::new("read_only", self.read_only));
+        entries.push(Entry::new("write_only", self.write_only));
+        entries.push(Entry::new("symlinks", self.symlinks.to_string()));
+        if let Some(strip_prefix) = &self.strip_prefix {
+            entries.push(Entry::new("strip_prefix", strip_prefix.as_str()));
+        }
+        entries.push(Entry::new("case_insensitive", self.case_insensitive));
+        entries.push(Entry::new("drop_denied", self.drop_denied));
+        entries.push(Entry::new("overwrite", self.overwrite.to_string()));
+        entries.push(Entry::new("create_dirs", self.create_dirs));
+        entries.push(Entry::new("dir_mode", mode_value(self.dir_mode)));
+        write_attributes(
+            entries,
+            self.file_mode,
+            None,
+            &self.file_owner,
+            &self.file_group,
+        );
+        entries.push(Entry::new("socket_activation", self.socket_activation));
+        entries.push(Entry::new("inetd", self.inetd));
+        if let Some(idle_timeout) = self.idle_timeout {
+            entries.push(Entry::new("idle_timeout", seconds_value(idle_timeout)));
+        }
+        if let Some(user) = &self.user {
+            entries.push(Entry::new("user", user.as_str()));
+        }
+        if let Some(group) = &self.group {
+            entries.push(Entry::new("group", group.as_str()));
+        }
+        entries.push(Entry::new("chroot", self.chroot));
+
+        let mut limits = Table::new("limits", false);
+        self.limits.write_entries(&mut limits.entries);
+        let mut retry = Table::new("retry", false);
+        self.retry.write_entries(&mut retry.entries);
+
+        let mut read_access = Table::new("read_access", false);
+        self.read_access.write_entries(&mut read_access.entries);
+        let mut write_access = Table::new("write_access", false);
+        self.write_access.write_entries(&mut write_access.entries);
+
+        let mut read_files = Table::new("read_files", false);
+        self.read_files.write_entries(&mut read_files.entries);
+        let mut write_files = Table::new("write_files", false);
+        self.write_files.write_entries(&mut write_files.entries);
+        let mut rate_limits = Table::new("rate_limits", false);
+        self.rate_limits.write_entries(&mut rate_limits.entries);
+        let mut quotas = Table::new("quotas", false);
+        self.quotas.write_entries(&mut quotas.entries);
+
+        let mut tables = vec![
+            root,
+            limits,
+            retry,
+            read_access,
+            write_access,
+            read_files,
+            write_files,
+            rate_limits,
+            quotas,
+        ];
+        tables.extend(self.listeners.iter().map(Listener::to_table));
+        tables.extend(self.profiles.iter().map(Profile::to_table));
+        tables.extend(self.mounts.iter().map(Mount::to_table));
+
+        tables
+    }
+
+    fn from_toml(text: &str) -> Result<Config, Box<dyn Error>> {
+        let mut config = Config::default();
+        let tables = toml::parse(text)?;
+
+        for table in &tables {
+            match (table.name.as_str(), table.array) {
+                ("", false) => {
+                    for entry in &table.entries {
+                        config.set_entry(entry)?;
+                    }
+                }
+                ("limits", false) => {
+                    for entry in &table.entries {
+                        config.limits.set_entry(entry)?;
+                    }
+                }
+                ("retry", false) => {
+                    for entry in &table.entries {
+                        config.retry.set_entry(entry)?;
+                    }
+                }
+                ("read_access", false) => {
+                    for entry in &table.entries {
+                        config.read_access.set_entry(entry)?;
+                    }
+                }
+                ("write_access", false) => {
+                    for entry in &table.entries {
+                        config.write_access.set_entry(entry)?;
+                    }
+                }
+                ("read_files", false) => {
+                    for entry in &table.entries {
+                        config.read_files.set_entry(entry)?;
+                    }
+                }
+                ("write_files", false) => {
+                    for entry in &table.entries {
+                        config.write_files.set_entry(entry)?;
+                    }
+                }
+                ("rate_limits", false) => {
+                    for entry in &table.entries {
+                        config.rate_limits.set_entry(entry)?;
+                    }
+                }
+                ("quotas", false) => {
+                    for entry in &table.entries {
+                        config.quotas.set_entry(entry)?;
+                    }
+                }
+                ("listener", true) => config.listeners.push(Listener::from_table(table)?),
+                ("profile", true) => {}
+                ("mount", true) => config.mounts.push(Mount::from_table(table)?),
+                _ => return Err(table.unknown()),
+            }
+        }
+
+        // Profiles are read last, so that the limits and retry policy they
+        // only partly override are complete.
+        for table in tables.iter().filter(|table| table.name == "profile") {
+            let profile = Profile::from_table(table, &config)?;
+            config.profiles.push(profile);
+        }
+
+        Ok(config)
+    }
+
+    fn set_entry(&mut self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        match entry.key.as_str() {
+            "ip_address" => self.ip_address = parse_entry(entry)?,
+            "port" => self.port = entry.as_integer()?,
+            "directory" => self.directory = directory_entry(entry)?,
+            "dual_stack" => self.dual_stack = entry.as_bool()?,
+            "single_port" => self.single_port = entry.as_bool()?,
+            "port_range" => {
+                self.port_range = Some(
+                    parse_port_range(entry.as_str()?)
+                        .map_err(|err| entry.error(&err.to_string()))?,
+                )
+            }
+            "read_only" => self.read_only = entry.as_bool()?,
+            "write_only" => self.write_only = entry.as_bool()?,
+            "symlinks" => self.symlinks = parse_entry(entry)?,
+            "strip_prefix" => self.strip_prefix = Some(entry.as_str()?.to_string()),
+            "case_insensitive" => self.case_insensitive = entry.as_bool()?,
+            "drop_denied" => self.drop_denied = entry.as_bool()?,
+            "overwrite" => self.overwrite = parse_entry(entry)?,
+            "create_dirs" => self.create_dirs = entry.as_bool()?,
+            "dir_mode" => self.dir_mode = mode_entry(entry)?,
+            "file_mode" => self.file_mode = Some(mode_entry(entry)?),
+            "file_owner" => self.file_owner = Some(entry.as_str()?.to_string()),
+            "file_group" => self.file_group = Some(entry.as_str()?.to_string()),
+            "socket_activation" => self.socket_activation = entry.as_bool()?,
+            "inetd" => self.inetd = entry.as_bool()?,
+            "idle_timeout" => self.idle_timeout = Some(Duration::from_secs(entry.as_integer()?)),
+            "user" => self.user = Some(entry.as_str()?.to_string()),
+            "group" => self.group = Some(entry.as_str()?.to_string()),
+            "chroot" => self.chroot = entry.as_bool()?,
+            _ => return Err(entry.unknown()),
+        }
+
+        Ok(())
+    }
+}
+
+impl Listener {
+    fn to_table(&self) -> Table {
+        let mut table = Table::new("listener", true);
+        table
+            .entries
+            .push(Entry::new("address", self.address.to_string()));
+        if let Some(directory) = &self.directory {
+            table
+                .entries
+                .push(Entry::new("directory", path_value(directory)));
+        }
+        if let Some(read_only) = self.read_only {
+            table.entries.push(Entry::new("read_only", read_only));
+        }
+        if let Some(write_only) = self.write_only {
+            table.entries.push(Entry::new("write_only", write_only));
+        }
+
+        table
+    }
+
+    fn from_table(table: &Table) -> Result<Listener, Box<dyn Error>> {
+        let mut address = None;
+        let mut directory = None;
+        let mut read_only = None;
+        let mut write_only = None;
+
+        for entry in &table.entries {
+            match entry.key.as_str() {
+                "address" => address = Some(parse_entry(entry)?),
+                "directory" => directory = Some(directory_entry(entry)?),
+                "read_only" => read_only = Some(entry.as_bool()?),
+                "write_only" => write_only = Some(entry.as_bool()?),
+                _ => return Err(entry.unknown()),
+            }
+        }
+
+        Ok(Listener {
+            address: address
+                .ok_or_else(|| format!("line {}: listener is missing an address", table.line))?,
+            directory,
+            read_only,
+            write_only,
+        })
+    }
+}
+
+impl Mount {
+    fn to_table(&self) -> Table {
+        let mut table = Table::new("mount", true);
+        table
+            .entries
+            .push(Entry::new("prefix", self.prefix.as_str()));
+        table
+            .entries
+            .push(Entry::new("directory", path_value(&self.directory)));
+        table.entries.push(Entry::new("read_only", self.read_only));
+        table
+            .entries
+            .push(Entry::new("write_only", self.write_only));
+        write_attributes(
+            &mut table.entries,
+            self.file_mode,
+            self.dir_mode,
+            &self.file_owner,
+            &self.file_group,
+        );
+
+        table
+    }
+
+    fn from_table(table: &Table) -> Result<Mount, Box<dyn Error>> {
+        let mut prefix = None;
+        let mut directory = None;
+        let mut mount = Mount::new("", "");
+
+        for entry in &table.entries {
+            match entry.key.as_str() {
+                "prefix" => prefix = Some(entry.as_str()?),
+                "directory" => directory = Some(directory_entry(entry)?),
+                "read_only" => mount.read_only = entry.as_bool()?,
+                "write_only" => mount.write_only = entry.as_bool()?,
+                "file_mode" => mount.file_mode = Some(mode_entry(entry)?),
+                "dir_mode" => mount.dir_mode = Some(mode_entry(entry)?),
+                "file_owner" => mount.file_owner = Some(entry.as_str()?.to_string()),
+                "file_group" => mount.file_group = Some(entry.as_str()?.to_string()),
+                _ => return Err(entry.unknown()),
+            }
+        }
+
+        let missing = |key| format!("line {}: mount is missing a {key}", table.line);
+        let location = Mount::new(
+            prefix.ok_or_else(|| missing("prefix"))?,
+            directory.ok_or_else(|| missing("directory"))?,
+        );
+        Ok(Mount {
+            prefix: location.prefix,
+            directory: location.directory,
+            ..mount
+        })
+    }
+}
+
+impl Profile {
+    fn to_table(&self) -> Table {
+        let mut table = Table::new("profile", true);
+        table
+            .entries
+            .push(Entry::new("clients", cidrs_value(&self.clients)));
+        if let Some(directory) = &self.directory {
+            table
+                .entries
+                .push(Entry::new("directory", path_value(directory)));
+        }
+        if let Some(read_only) = self.read_only {
+            table.entries.push(Entry::new("read_only", read_only));
+        }
+        if let Some(write_only) = self.write_only {
+            table.entries.push(Entry::new("write_only", write_only));
+        }
+        if let Some(limits) = &self.limits {
+            limits.write_entries(&mut table.entries);
+        }
+        if let Some(retry) = &self.retry {
+            retry.write_entries(&mut table.entries);
+        }
+        write_attributes(
+            &mut table.entries,
+            self.file_mode,
+            self.dir_mode,
+            &self.file_owner,
+            &self.file_group,
+        );
+
+        table
+    }
+
+    fn from_table(table: &Table, config: &Config) -> Result<Profile, Box<dyn Error>> {
+        let mut profile = Profile::new(Vec::new());
+
+        for entry in &table.entries {
+            match entry.key.as_str() {
+                "clients" => profile.clients = cidrs_entry(entry)?,
+                "directory" => profile.directory = Some(directory_entry(entry)?),
+                "read_only" => profile.read_only = Some(entry.as_bool()?),
+                "write_only" => profile.write_only = Some(entry.as_bool()?),
+                "file_mode" => profile.file_mode = Some(mode_entry(entry)?),
+                "dir_mode" => profile.dir_mode = Some(mode_entry(entry)?),
+                "file_owner" => profile.file_owner = Some(entry.as_str()?.to_string()),
+                "file_group" => profile.file_group = Some(entry.as_str()?.to_string()),
+                "max_block_size" | "max_window_size" => profile
+                    .limits
+                    .get_or_insert(config.limits)
+                    .set_entry(entry)?,
+                "max_retries" | "timeout" => {
+                    profile.retry.get_or_insert(config.retry).set_entry(entry)?
+                }
+                _ => return Err(entry.unknown()),
+            }
+        }
+
+        if profile.clients.is_empty() {
+            return Err(format!("line {}: profile is missing its clients", table.line).into());
+        }
+
+        Ok(profile)
+    }
+}
+
+impl AccessList {
+    fn write_entries(&self, entries: &mut Vec<Entry>) {
+        if !self.allow.is_empty() {
+            entries.push(Entry::new("allow", cidrs_value(&self.allow)));
+        }
+        if !self.deny.is_empty() {
+            entries.push(Entry::new("deny", cidrs_value(&self.deny)));
+        }
+    }
+
+    fn set_entry(&mut self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        match entry.key.as_str() {
+            "allow" => self.allow = cidrs_entry(entry)?,
+            "deny" => self.deny = cidrs_entry(entry)?,
+            _ => return Err(entry.unknown()),
+        }
+
+        Ok(())
+    }
+}
+
+impl FileFilter {
+    fn write_entries(&self, entries: &mut Vec<Entry>) {
+        if !self.allow.is_empty() {
+            entries.push(Entry::new("allow", strings_value(&self.allow)));
+        }
+        if !self.deny.is_empty() {
+            entries.push(Entry::new("deny", strings_value(&self.deny)));
+        }
+    }
+
+    fn set_entry(&mut self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        let patterns = entry
+            .as_str_array()?
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        match entry.key.as_str() {
+            "allow" => self.allow = patterns,
+            "deny" => self.deny = patterns,
+            _ => return Err(entry.unknown()),
+        }
+
+        Ok(())
+    }
+}
+
+impl RateLimits {
+    fn write_entries(&self, entries: &mut Vec<Entry>) {
+        if let Some(client) = &self.client {
+            entries.push(Entry::new("client", client.to_string()));
+        }
+        if let Some(global) = &self.global {
+            entries.push(Entry::new("global", global.to_string()));
+        }
+        if let Some(ban_after) = self.ban_after {
+            entries.push(Entry::new("ban_after", i64::from(ban_after)));
+        }
+        entries.push(Entry::new("ban_duration", seconds_value(self.ban_duration)));
+    }
+
+    fn set_entry(&mut self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        match entry.key.as_str() {
+            "client" => self.client = Some(parse_entry(entry)?),
+            "global" => self.global = Some(parse_entry(entry)?),
+            "ban_after" => self.ban_after = Some(entry.as_integer()?),
+            "ban_duration" => self.ban_duration = Duration::from_secs(entry.as_integer()?),
+            _ => return Err(entry.unknown()),
+        }
+
+        Ok(())
+    }
+}
+
+impl Quotas {
+    fn write_entries(&self, entries: &mut Vec<Entry>) {
+        let limits = [
+            ("max_file_size", self.max_file_size),
+            ("client_daily_bytes", self.client_daily_bytes),
+            ("min_free_space", self.min_free_space),
+        ];
+        for (key, bytes) in limits {
+            if let Some(bytes) = bytes {
+                entries.push(Entry::new(key, i64::try_from(bytes).unwrap_or(i64::MAX)));
+            }
+        }
+    }
+
+    fn set_entry(&mut self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        match entry.key.as_str() {
+            "max_file_size" => self.max_file_size = Some(entry.as_integer()?),
+            "client_daily_bytes" => self.client_daily_bytes = Some(entry.as_integer()?),
+            "min_free_space" => self.min_free_space = Some(entry.as_integer()?),
+            _ => return Err(entry.unknown()),
+        }
+
+        Ok(())
+    }
+}
+
+impl RetryPolicy {
+    fn write_entries(&self, entries: &mut Vec<Entry>) {
+        entries.push(Entry::new("max_retries", i64::from(self.max_retries)));
+        entries.push(Entry::new("timeout", seconds_value(self.timeout)));
+    }
+
+    fn set_entry(&mut self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        match entry.key.as_str() {
+            "max_retries" => self.max_retries = entry.as_integer()?,
+            "timeout" => self.timeout = Duration::from_secs(entry.as_integer()?),
+            _ => return Err(entry.unknown()),
+        }
+
+        Ok(())
+    }
+}
+
+impl OptionLimits {
+    fn write_entries(&self, entries: &mut Vec<Entry>) {
+        entries.push(Entry::new(
+            "max_block_size",
+            i64::try_from(self.max_block_size).unwrap_or(i64::MAX),
+        ));
+        entries.push(Entry::new(
+            "max_window_size",
+            i64::from(self.max_window_size),
+        ));
+    }
+
+    fn set_entry(&mut self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        match entry.key.as_str() {
+            "max_block_size" => self.max_block_size = entry.as_integer()?,
+            "max_window_size" => self.max_window_size = entry.as_integer()?,
+            _ => return Err(entry.unknown()),
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_entry<T>(entry: &Entry) -> Result<T, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    entry
+        .as_str()?
+        .parse()
+        .map_err(|err| entry.error(&format!("invalid {}: {err}", entry.key)))
+}
+
+/// Returns whether `directory` exists. After a chroot, paths still name the
+/// directories as they were found outside of it.
+fn directory_exists(directory: &Path) -> bool {
+    sys::path_in_root(directory).is_some_and(|directory| directory.exists())
+}
+
+fn directory_entry(entry: &Entry) -> Result<PathBuf, Box<dyn Error>> {
+    let directory = PathBuf::from(entry.as_str()?);
+    if !directory_exists(&directory) {
+        return Err(entry.error(&format!("{} does not exist", directory.display())));
+    }
+
+    Ok(directory)
+}
+
+/// Writes the modes and owner of uploaded files that are set.
+fn write_attributes(
+    entries: &mut Vec<Entry>,
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    file_owner: &Option<String>,
+    file_group: &Option<String>,
+) {
+    if let Some(file_mode) = file_mode {
+        entries.push(Entry::new("file_mode", mode_value(file_mode)));
+    }
+    if let Some(dir_mode) = dir_mode {
+        entries.push(Entry::new("dir_mode", mode_value(dir_mode)));
+    }
+    if let Some(file_owner) = file_owner {
+        entries.push(Entry::new("file_owner", file_owner.as_str()));
+    }
+    if let Some(file_group) = file_group {
+        entries.push(Entry::new("file_group", file_group.as_str()));
+    }
+}
+
+fn mode_entry(entry: &Entry) -> Result<u32, Box<dyn Error>> {
+    parse_mode(entry.as_str()?).map_err(|err| entry.error(&err.to_string()))
+}
+
+fn cidrs_entry(entry: &Entry) -> Result<Vec<Cidr>, Box<dyn Error>> {
+    entry
+        .as_str_array()?
+        .iter()
+        .map(|cidr| {
+            cidr.parse()
+                .map_err(|err| entry.error(&format!("invalid client {cidr}: {err}")))
+        })
+        .collect()
+}
+
+fn cidrs_value(cidrs: &[Cidr]) -> Value {
+    Value::from(
+        cidrs
+            .iter()
+            .map(|cidr| Value::from(cidr.to_string()))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn strings_value(strings: &[String]) -> Value {
+    Value::from(
+        strings
+            .iter()
+            .map(|string| Value::from(string.as_str()))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn path_value(path: &Path) -> Value {
+    Value::from(path.to_string_lossy().into_owned())
+}
+
+fn mode_value(mode: u32) -> Value {
+    Value::from(format!("{mode:04o}"))
+}
+
+fn seconds_value(duration: Duration) -> Value {
+    Value::from(i64::try_from(duration.as_secs()).unwrap_or(i64::MAX))
+}
+
+/// Parses octal permission bits such as `0750`.
+fn parse_mode(value: &str) -> Result<u32, Box<dyn Error>> {
+    match u32::from_str_radix(value, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("Invalid mode: {value}").into()),
+    }
+}
+
+fn parse_port_range(value: &str) -> Result<RangeInclusive<u16>, Box<dyn Error>> {
+    let (start, end) = value
+        .split_once(':')
+        .ok_or("Port range must be in the form START:END")?;
+    let (start, end) = (start.parse::<u16>()?, end.parse::<u16>()?);
+
+    if start == 0 || start > end {
+        return Err(format!("Invalid port range: {value}").into());
+    }
+
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    #[test]
+    fn parses_full_config() {
+        let config = Config::new(
+            ["/", "-i", "0.0.0.0", "-p", "1234", "-d", "/", "-s", "-r"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.ip_address, Ipv4Addr::new(0, 0, 0, 0));
+        assert_eq!(config.port, 1234);
         assert_eq!(config.directory, PathBuf::from_str("/").unwrap());
         assert!(config.single_port);
         assert!(config.read_only);
@@ -124,7 +1925,7 @@ mod tests {
     #[test]
     fn parses_some_config() {
         let config = Config::new(
-            vec!["/", "-i", "0.0.0.0", "-d", "/"]
+            ["/", "-i", "0.0.0.0", "-d", "/"]
                 .iter()
                 .map(|s| s.to_string()),
         )
@@ -135,10 +1936,755 @@ mod tests {
         assert_eq!(config.directory, PathBuf::from_str("/").unwrap());
     }
 
+    #[test]
+    fn parses_ipv6_config() {
+        let config = Config::new(["/", "-i", "::1", "-6"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.ip_address, Ipv6Addr::LOCALHOST);
+        assert!(config.dual_stack);
+    }
+
+    #[test]
+    fn parses_listeners() {
+        let config = Config::new(
+            [
+                "/",
+                "-l",
+                "127.0.0.1:6969",
+                "--listen",
+                "[::1]:69,/,read-only",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.listeners,
+            vec![
+                Listener::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 6969))),
+                Listener {
+                    address: SocketAddr::from((Ipv6Addr::LOCALHOST, 69)),
+                    directory: Some(PathBuf::from("/")),
+                    read_only: Some(true),
+                    write_only: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_write_only_mode() {
+        let config = Config::new(
+            ["/", "-w", "-l", "127.0.0.1:69,read-write"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert!(config.write_only);
+        assert_eq!(config.listeners[0].read_only, Some(false));
+        assert_eq!(config.listeners[0].write_only, Some(false));
+
+        let config = Config::from_toml(
+            "write_only = true\n[[profile]]\nclients = [\"10.0.0.0/8\"]\nwrite_only = false",
+        )
+        .unwrap();
+        assert!(config.write_only);
+        assert_eq!(config.profiles[0].write_only, Some(false));
+    }
+
+    #[test]
+    fn parses_symlink_policy() {
+        let config =
+            Config::new(["/", "--symlinks", "deny"].iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(config.symlinks, SymlinkPolicy::Deny);
+
+        let config = Config::from_toml("symlinks = \"any\"").unwrap();
+        assert_eq!(config.symlinks, SymlinkPolicy::Any);
+
+        assert!(Config::new(["/", "--symlinks", "all"].iter().map(|s| s.to_string())).is_err());
+        assert!(Config::from_toml("symlinks = \"outside\"").is_err());
+    }
+
+    #[test]
+    fn parses_overwrite_policy() {
+        let config =
+            Config::new(["/", "--overwrite", "always"].iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(config.overwrite, OverwritePolicy::Always);
+
+        let config = Config::from_toml("overwrite = \"keep-versions:5\"").unwrap();
+        assert_eq!(config.overwrite, OverwritePolicy::KeepVersions(5));
+
+        let config = Config::from_toml("overwrite = \"older-than:30\"").unwrap();
+        assert_eq!(
+            config.overwrite,
+            OverwritePolicy::OlderThan(Duration::from_secs(1800))
+        );
+        assert_eq!(config.overwrite.to_string(), "older-than:30");
+
+        for invalid in [
+            "sometimes",
+            "older-than",
+            "older-than:soon",
+            "keep-versions:0",
+        ] {
+            assert!(invalid.parse::<OverwritePolicy>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_access_lists() {
+        let config = Config::new(
+            [
+                "/",
+                "--allow-read",
+                "10.0.0.0/8",
+                "--deny-read",
+                "10.9.0.0/16",
+                "--deny-write",
+                "0.0.0.0/0",
+                "--drop-denied",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            config.read_access.allow,
+            vec!["10.0.0.0/8".parse().unwrap()]
+        );
+        assert_eq!(
+            config.read_access.deny,
+            vec!["10.9.0.0/16".parse().unwrap()]
+        );
+        assert!(config.write_access.allow.is_empty());
+        assert_eq!(config.write_access.deny, vec!["0.0.0.0/0".parse().unwrap()]);
+        assert!(config.drop_denied);
+
+        let config = Config::from_toml(
+            "drop_denied = true\n[write_access]\nallow = [\"10.2.0.0/16\", \"fd00::/8\"]",
+        )
+        .unwrap();
+        assert!(config.drop_denied);
+        assert_eq!(config.write_access.allow.len(), 2);
+        assert_eq!(config.read_access, AccessList::default());
+
+        let client = "10.9.1.1".parse().unwrap();
+        let access = AccessList {
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.9.0.0/16".parse().unwrap()],
+        };
+        assert_eq!(
+            access.denied_by(&client).as_deref(),
+            Some("deny 10.9.0.0/16")
+        );
+        assert_eq!(
+            access.denied_by(&"192.168.1.1".parse().unwrap()).as_deref(),
+            Some("no allow rule")
+        );
+        assert_eq!(access.denied_by(&"10.1.1.1".parse().unwrap()), None);
+        assert!(Config::from_toml("[read_access]\ndeny = [\"10.0.0.0/33\"]").is_err());
+    }
+
+    #[test]
+    fn parses_file_filters() {
+        let config = Config::new(
+            [
+                "/",
+                "--deny-read-file",
+                ".git/",
+                "--deny-read-file",
+                "*.key",
+                "--allow-write-file",
+                "dumps/*.core",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+        assert_eq!(config.read_files.deny, vec![".git/", "*.key"]);
+        assert!(config.read_files.allow.is_empty());
+        assert_eq!(config.write_files.allow, vec!["dumps/*.core"]);
+
+        let config = Config::from_toml("[read_files]\ndeny = [\".*\", \"*~\"]").unwrap();
+        assert_eq!(config.read_files.deny, vec![".*", "*~"]);
+        assert!(Config::from_toml("[write_files]\nallow = \"*.core\"").is_err());
+    }
+
+    #[test]
+    fn parses_rate_limits() {
+        let config = Config::new(
+            [
+                "/",
+                "--rate-limit",
+                "5/20",
+                "--global-rate-limit",
+                "100",
+                "--ban-after",
+                "50",
+                "--ban-duration",
+                "300",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            config.rate_limits,
+            RateLimits {
+                client: Some(RateLimit { rate: 5, burst: 20 }),
+                global: Some(RateLimit {
+                    rate: 100,
+                    burst: 100
+                }),
+                ban_after: Some(50),
+                ban_duration: Duration::from_secs(300),
+            }
+        );
+
+        let config = Config::from_toml("[rate_limits]\nclient = \"2/4\"\nban_after = 10").unwrap();
+        assert_eq!(
+            config.rate_limits.client,
+            Some(RateLimit { rate: 2, burst: 4 })
+        );
+        assert_eq!(config.rate_limits.ban_after, Some(10));
+        assert_eq!(config.rate_limits.ban_duration, DEFAULT_BAN_DURATION);
+
+        for limit in ["", "5/", "/5", "-1", "five"] {
+            assert!(limit.parse::<RateLimit>().is_err(), "{limit}");
+        }
+    }
+
+    #[test]
+    fn parses_upload_attributes() {
+        let config = Config::new(
+            [
+                "/",
+                "--file-mode",
+                "0640",
+                "--file-owner",
+                "tftp",
+                "--file-group",
+                "builders",
+                "-m",
+                "ci=/,write-only,file-mode=0644,dir-mode=2750,owner=ci,group=1000",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+        assert_eq!(config.file_mode, Some(0o640));
+        assert_eq!(config.file_owner.as_deref(), Some("tftp"));
+        assert_eq!(config.file_group.as_deref(), Some("builders"));
+        assert_eq!(
+            config.mounts,
+            vec![Mount {
+                write_only: true,
+                file_mode: Some(0o644),
+                dir_mode: Some(0o2750),
+                file_owner: Some("ci".to_string()),
+                file_group: Some("1000".to_string()),
+                ..Mount::new("ci", "/")
+            }]
+        );
+
+        let config = Config::from_toml(
+            "file_mode = \"0600\"\n[[mount]]\nprefix = \"ci\"\ndirectory = \"/\"\nfile_group = \"builders\"\n[[profile]]\nclients = [\"10.0.0.0/8\"]\nfile_mode = \"0640\"\ndir_mode = \"0750\"\nfile_owner = \"ci\"",
+        )
+        .unwrap();
+        assert_eq!(config.file_mode, Some(0o600));
+        assert_eq!(config.mounts[0].file_group.as_deref(), Some("builders"));
+        assert_eq!(
+            config.profiles,
+            vec![Profile {
+                file_mode: Some(0o640),
+                dir_mode: Some(0o750),
+                file_owner: Some("ci".to_string()),
+                ..Profile::new(vec!["10.0.0.0/8".parse().unwrap()])
+            }]
+        );
+
+        assert!("ci=/,file-mode=rw".parse::<Mount>().is_err());
+        assert!("ci=/,mode=0644".parse::<Mount>().is_err());
+    }
+
+    #[test]
+    fn parses_directory_creation() {
+        let config = Config::new(
+            ["/", "--create-dirs", "--dir-mode", "0750"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+        assert!(config.create_dirs);
+        assert_eq!(config.dir_mode, 0o750);
+
+        let config = Config::from_toml("create_dirs = true\ndir_mode = \"2770\"").unwrap();
+        assert!(config.create_dirs);
+        assert_eq!(config.dir_mode, 0o2770);
+        assert_eq!(Config::default().dir_mode, 0o755);
+
+        for mode in ["", "0758", "17777", "rwx"] {
+            assert!(parse_mode(mode).is_err(), "{mode}");
+        }
+        assert!(Config::from_toml("dir_mode = 750").is_err());
+    }
+
+    #[test]
+    fn parses_quotas() {
+        let config = Config::new(
+            [
+                "/",
+                "--max-file-size",
+                "1048576",
+                "--client-daily-quota",
+                "10485760",
+                "--min-free-space",
+                "0",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            config.quotas,
+            Quotas {
+                max_file_size: Some(1048576),
+                client_daily_bytes: Some(10485760),
+                min_free_space: Some(0),
+            }
+        );
+
+        let config = Config::from_toml(
+            "[quotas]
+max_file_size = 4096",
+        )
+        .unwrap();
+        assert_eq!(config.quotas.max_file_size, Some(4096));
+        assert_eq!(config.quotas.client_daily_bytes, None);
+
+        assert!(Config::new(["/", "--max-file-size", "1M"].iter().map(|s| s.to_string())).is_err());
+        assert!(Config::from_toml(
+            "[quotas]
+max_file_size = -1"
+        )
+        .is_err());
+        assert!(Config::from_toml(
+            "[quotas]
+max_size = 1"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_file_name_settings() {
+        let config = Config::new(
+            ["/", "--strip-prefix", "/tftpboot/", "--case-insensitive"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+        assert_eq!(config.strip_prefix.as_deref(), Some("/tftpboot/"));
+        assert!(config.case_insensitive);
+
+        let config =
+            Config::from_toml("strip_prefix = \"/tftpboot\"\ncase_insensitive = true").unwrap();
+        assert_eq!(config.strip_prefix.as_deref(), Some("/tftpboot"));
+        assert!(config.case_insensitive);
+    }
+
+    #[test]
+    fn parses_mounts() {
+        let config = Config::new(
+            [
+                "/",
+                "-m",
+                "/boot/=/,read-only",
+                "--mount",
+                "dumps=/,write-only",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.mounts,
+            vec![
+                Mount {
+                    read_only: true,
+                    ..Mount::new("boot", "/")
+                },
+                Mount {
+                    write_only: true,
+                    ..Mount::new("dumps", "/")
+                },
+            ]
+        );
+
+        for mount in ["boot", "boot=/does/not/exist", "boot=/,read-write"] {
+            assert!(Config::new(["/", "-m", mount].iter().map(|s| s.to_string())).is_err());
+        }
+    }
+
+    #[test]
+    fn matches_mount_prefixes() {
+        let mount = Mount::new("fw/", "/");
+
+        assert_eq!(
+            mount.strip_prefix("fw/board/image", false),
+            Some("board/image")
+        );
+        assert_eq!(mount.strip_prefix("/fw/image", false), Some("image"));
+        assert_eq!(mount.strip_prefix("fw", false), Some(""));
+        assert_eq!(mount.strip_prefix("firmware/image", false), None);
+        assert_eq!(mount.strip_prefix("fwimage", false), None);
+        assert_eq!(mount.strip_prefix("FW/image", false), None);
+        assert_eq!(mount.strip_prefix("FW/image", true), Some("image"));
+    }
+
+    #[test]
+    fn returns_error_on_invalid_listener() {
+        for listener in [
+            "127.0.0.1",
+            "127.0.0.1:69,/,/",
+            "127.0.0.1:69,/does/not/exist",
+        ] {
+            assert!(Config::new(["/", "-l", listener].iter().map(|s| s.to_string())).is_err());
+        }
+    }
+
+    #[test]
+    fn parses_port_range() {
+        let config = Config::new(
+            ["/", "--port-range", "50000:50100"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.port_range, Some(50000..=50100));
+    }
+
+    #[test]
+    fn returns_error_on_invalid_port_range() {
+        for range in ["50000", "50100:50000", "0:100", "1:65536"] {
+            assert!(
+                Config::new(["/", "--port-range", range].iter().map(|s| s.to_string())).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn parses_socket_activation() {
+        let config = Config::new(
+            ["/", "--socket-activation", "--idle-timeout", "30"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert!(config.socket_activation);
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn parses_inetd_mode() {
+        let config = Config::new(["/", "--inetd"].iter().map(|s| s.to_string())).unwrap();
+
+        assert!(config.inetd);
+        assert_eq!(config.idle_timeout, None);
+    }
+
+    #[test]
+    fn parses_privilege_settings() {
+        let config = Config::new(
+            ["/", "-u", "tftp", "--group", "builders", "--chroot"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.user.as_deref(), Some("tftp"));
+        assert_eq!(config.group.as_deref(), Some("builders"));
+        assert!(config.chroot);
+    }
+
+    #[test]
+    fn parses_config_file() {
+        let config = Config::from_toml(
+            r#"
+ip_address = "0.0.0.0"
+port = 6969
+directory = "/"
+read_only = true
+port_range = "50000:50100"
+idle_timeout = 60
+user = "tftp"
+
+[limits]
+max_block_size = 1468
+
+[[listener]]
+address = "[::1]:69"
+read_only = false
+
+[[listener]]
+address = "127.0.0.1:69"
+directory = "/"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.ip_address, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(config.port, 6969);
+        assert_eq!(config.directory, PathBuf::from("/"));
+        assert!(config.read_only);
+        assert_eq!(config.port_range, Some(50000..=50100));
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(config.user.as_deref(), Some("tftp"));
+        assert_eq!(config.limits.max_block_size, 1468);
+        assert_eq!(config.limits.max_window_size, u16::MAX);
+        assert_eq!(
+            config.listeners,
+            vec![
+                Listener {
+                    address: SocketAddr::from((Ipv6Addr::LOCALHOST, 69)),
+                    directory: None,
+                    read_only: Some(false),
+                    write_only: None,
+                },
+                Listener {
+                    address: SocketAddr::from((Ipv4Addr::LOCALHOST, 69)),
+                    directory: Some(PathBuf::from("/")),
+                    read_only: None,
+                    write_only: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_profiles_in_config_file() {
+        let config = Config::from_toml(
+            r#"
+[[profile]]
+clients = ["10.1.0.0/16"]
+directory = "/"
+read_only = true
+
+[[profile]]
+clients = ["10.2.0.0/16", "fd00::/8"]
+read_only = false
+max_block_size = 8192
+max_retries = 10
+
+[limits]
+max_window_size = 16
+
+[retry]
+timeout = 2
+
+[[mount]]
+prefix = "dumps/"
+directory = "/"
+write_only = true
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.retry.timeout, Duration::from_secs(2));
+        assert_eq!(
+            config.mounts,
+            vec![Mount {
+                write_only: true,
+                ..Mount::new("dumps", "/")
+            }]
+        );
+        assert_eq!(
+            config.profiles,
+            vec![
+                Profile {
+                    directory: Some(PathBuf::from("/")),
+                    read_only: Some(true),
+                    ..Profile::new(vec!["10.1.0.0/16".parse().unwrap()])
+                },
+                Profile {
+                    read_only: Some(false),
+                    limits: Some(OptionLimits {
+                        max_block_size: 8192,
+                        max_window_size: 16,
+                    }),
+                    retry: Some(RetryPolicy {
+                        max_retries: 10,
+                        timeout: Duration::from_secs(2),
+                    }),
+                    ..Profile::new(vec![
+                        "10.2.0.0/16".parse().unwrap(),
+                        "fd00::/8".parse().unwrap(),
+                    ])
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_config_that_reads_back() {
+        let config = Config::from_toml(
+            r#"
+directory = "/"
+port_range = "50000:50100"
+idle_timeout = 30
+user = "tftp"
+
+[limits]
+max_block_size = 1468
+
+[[listener]]
+address = "[::1]:69"
+write_only = true
+
+[[profile]]
+clients = ["10.1.0.0/16", "fd00::/8"]
+read_only = true
+max_retries = 3
+
+[[mount]]
+prefix = "boot"
+directory = "/"
+read_only = true
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(Config::from_toml(&config.to_toml()).unwrap(), config);
+
+        let json = config.to_json();
+        assert!(json.starts_with("{\n  \"ip_address\": \"127.0.0.1\",\n"));
+        assert!(json.contains("\"clients\": [\"10.1.0.0/16\", \"fd00::/8\"]"));
+        assert!(json.contains("\"mount\": [\n    {\n      \"prefix\": \"boot\","));
+    }
+
+    #[test]
+    fn reports_problems_when_checking() {
+        let config = Config {
+            directory: PathBuf::from("/"),
+            read_only: true,
+            ..Config::default()
+        };
+        assert_eq!(config.check(), Vec::<String>::new());
+
+        let config = Config {
+            directory: PathBuf::from("/this/does/not/exist"),
+            read_only: true,
+            profiles: vec![
+                Profile::new(vec!["10.0.0.0/8".parse().unwrap()]),
+                Profile::new(vec![
+                    "192.168.0.0/16".parse().unwrap(),
+                    "10.1.0.0/16".parse().unwrap(),
+                ]),
+            ],
+            mounts: vec![
+                Mount::new("boot", "/"),
+                Mount::new("fw", "/tmp"),
+                Mount::new("boot/efi", "/proc"),
+                Mount::new("bootstrap", "/sys"),
+            ],
+            ..Config::default()
+        };
+        assert_eq!(
+            config.check(),
+            vec![
+                "/this/does/not/exist is not a directory",
+                "Mounts boot and fw overlap in /tmp",
+                "Mounts boot and boot/efi overlap in /proc",
+                "Mounts boot and bootstrap overlap in /sys",
+                "Client network 10.1.0.0/16 of profile 2 overlaps 10.0.0.0/8 of profile 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn overrides_config_file_with_flags() {
+        let file = env::temp_dir().join(format!("tftpd-config-{}.toml", process::id()));
+        fs::write(
+            &file,
+            "port = 6969
+read_only = true
+[[listener]]
+address = \"127.0.0.1:69\"
+",
+        )
+        .unwrap();
+
+        let config = Config::new(
+            [
+                "/",
+                "-p",
+                "1234",
+                "--config",
+                file.to_str().unwrap(),
+                "-l",
+                "127.0.0.1:6969",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(config.port, 1234);
+        assert!(config.read_only);
+        assert_eq!(
+            config.listeners,
+            vec![Listener::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 6969)))]
+        );
+    }
+
+    #[test]
+    fn returns_error_on_invalid_config_file() {
+        for (text, error) in [
+            ("port = 69\nprot = 70", "line 2: unknown key prot"),
+            (
+                "[limits]\nmax_blocksize = 8",
+                "line 2: unknown key max_blocksize",
+            ),
+            ("[[profiles]]", "line 1: unknown table profiles"),
+            (
+                "[[mount]]\nprefix = \"boot\"",
+                "line 1: mount is missing a directory",
+            ),
+            (
+                "[[profile]]\nread_only = true",
+                "line 1: profile is missing its clients",
+            ),
+            (
+                "[[profile]]\nclients = [\"10.0.0.0/33\"]",
+                "line 2: invalid client 10.0.0.0/33: Invalid prefix length 33 for 10.0.0.0",
+            ),
+            (
+                "[[listener]]\nread_only = true",
+                "line 1: listener is missing an address",
+            ),
+            ("port = \"69\"", "line 1: port must be an integer"),
+            ("\nport_range = \"5:1\"", "line 2: Invalid port range: 5:1"),
+            (
+                "directory = \"/this/does/not/exist\"",
+                "line 1: /this/does/not/exist does not exist",
+            ),
+        ] {
+            assert_eq!(Config::from_toml(text).unwrap_err().to_string(), error);
+        }
+
+        assert!(Config::new(["/", "--config"].iter().map(|s| s.to_string())).is_err());
+        assert!(Config::from_file("/this/does/not/exist.toml").is_err());
+    }
+
     #[test]
     fn returns_error_on_invalid_ip() {
         assert!(Config::new(
-            vec!["/", "-i", "1234.5678.9012.3456"]
+            ["/", "-i", "1234.5678.9012.3456"]
                 .iter()
                 .map(|s| s.to_string()),
         )
@@ -147,13 +2693,13 @@ mod tests {
 
     #[test]
     fn returns_error_on_invalid_port() {
-        assert!(Config::new(vec!["/", "-p", "1234567"].iter().map(|s| s.to_string()),).is_err());
+        assert!(Config::new(["/", "-p", "1234567"].iter().map(|s| s.to_string()),).is_err());
     }
 
     #[test]
     fn returns_error_on_invalid_directory() {
         assert!(Config::new(
-            vec!["/", "-d", "/this/does/not/exist"]
+            ["/", "-d", "/this/does/not/exist"]
                 .iter()
                 .map(|s| s.to_string()),
         )
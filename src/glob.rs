@@ -0,0 +1,284 @@
+use crate::FileFilter;
+use std::error::Error;
+
+/// A compiled shell-style pattern for requested file names.
+///
+/// `*` matches any characters except `/`, `**` matches any characters, `?`
+/// matches a single character except `/` and `[...]` matches one of a set
+/// of characters, such as `[a-z_]` or `[!0-9]`.
+///
+/// Patterns without a `/` match the file name in any directory, like
+/// `*.key`, while patterns with a `/` match the whole path from the served
+/// directory, like `dumps/*.core`. Patterns ending in `/`, like `.git/`,
+/// match directories and so every file below them.
+#[derive(Debug)]
+pub(crate) struct Pattern {
+    text: String,
+    tokens: Vec<Token>,
+    anchored: bool,
+    directory: bool,
+}
+
+#[derive(Debug)]
+enum Token {
+    Char(char),
+    AnyChar,
+    Star,
+    DoubleStar,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+/// Compiled allow and deny patterns of a [`FileFilter`].
+#[derive(Debug, Default)]
+pub(crate) struct Rules {
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+}
+
+impl Pattern {
+    pub(crate) fn new(text: &str) -> Result<Pattern, Box<dyn Error>> {
+        let directory = text.ends_with('/');
+        let trimmed = text.trim_end_matches('/');
+        let anchored = trimmed.contains('/');
+        let trimmed = trimmed.trim_start_matches('/');
+        if trimmed.is_empty() {
+            return Err(format!("Invalid file pattern: {text}").into());
+        }
+
+        let mut tokens = Vec::new();
+        let mut chars = trimmed.chars().peekable();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*' if chars.next_if_eq(&'*').is_some() => Token::DoubleStar,
+                '*' => Token::Star,
+                '?' => Token::AnyChar,
+                '[' => {
+                    let negated = chars.next_if(|&c| c == '!' || c == '^').is_some();
+                    let mut class = Vec::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') if !class.is_empty() => break,
+                            Some(c) => class.push(c),
+                            None => {
+                                return Err(format!("Unclosed [ in file pattern: {text}").into())
+                            }
+                        }
+                    }
+
+                    let mut ranges = Vec::new();
+                    let mut index = 0;
+                    while index < class.len() {
+                        if index + 2 < class.len() && class[index + 1] == '-' {
+                            ranges.push((class[index], class[index + 2]));
+                            index += 3;
+                        } else {
+                            ranges.push((class[index], class[index]));
+                            index += 1;
+                        }
+                    }
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+        }
+
+        Ok(Pattern {
+            text: text.to_string(),
+            tokens,
+            anchored,
+            directory,
+        })
+    }
+
+    /// Returns whether the normalized relative `path` matches the pattern,
+    /// optionally ignoring ASCII case.
+    pub(crate) fn matches(&self, path: &str, ignore_case: bool) -> bool {
+        let segments = path.split('/').collect::<Vec<_>>();
+        let Some((name, directories)) = segments.split_last() else {
+            return false;
+        };
+
+        match (self.anchored, self.directory) {
+            (false, false) => self.matches_text(name, ignore_case),
+            (false, true) => directories
+                .iter()
+                .any(|directory| self.matches_text(directory, ignore_case)),
+            (true, false) => self.matches_text(path, ignore_case),
+            (true, true) => (1..segments.len())
+                .any(|count| self.matches_text(&segments[..count].join("/"), ignore_case)),
+        }
+    }
+
+    fn matches_text(&self, text: &str, ignore_case: bool) -> bool {
+        let text = text.chars().collect::<Vec<_>>();
+
+        // matched[i] tells whether the tokens so far match the first i
+        // characters of the text.
+        let mut matched = vec![false; text.len() + 1];
+        matched[0] = true;
+        for token in &self.tokens {
+            let mut next = vec![false; text.len() + 1];
+            for i in 0..=text.len() {
+                next[i] = match token {
+                    Token::Star => matched[i] || (i > 0 && next[i - 1] && text[i - 1] != '/'),
+                    Token::DoubleStar => matched[i] || (i > 0 && next[i - 1]),
+                    _ if i == 0 => false,
+                    token => matched[i - 1] && token.matches_char(text[i - 1], ignore_case),
+                };
+            }
+            matched = next;
+        }
+
+        matched[text.len()]
+    }
+}
+
+impl Token {
+    fn matches_char(&self, c: char, ignore_case: bool) -> bool {
+        match self {
+            Token::Char(expected) if ignore_case => expected.eq_ignore_ascii_case(&c),
+            Token::Char(expected) => *expected == c,
+            Token::AnyChar => c != '/',
+            Token::Class { negated, ranges } => {
+                let contains = |c: char| {
+                    ranges
+                        .iter()
+                        .any(|(start, end)| (*start..=*end).contains(&c))
+                };
+                let found = contains(c)
+                    || ignore_case
+                        && (contains(c.to_ascii_lowercase()) || contains(c.to_ascii_uppercase()));
+                c != '/' && found != *negated
+            }
+            Token::Star | Token::DoubleStar => false,
+        }
+    }
+}
+
+impl Rules {
+    pub(crate) fn new(filter: &FileFilter) -> Result<Rules, Box<dyn Error>> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Rules {
+            allow: compile(&filter.allow)?,
+            deny: compile(&filter.deny)?,
+        })
+    }
+
+    /// Returns the rule that denies the normalized relative `path`, if any.
+    pub(crate) fn denied_by(&self, path: &str, ignore_case: bool) -> Option<String> {
+        if let Some(pattern) = self
+            .deny
+            .iter()
+            .find(|pattern| pattern.matches(path, ignore_case))
+        {
+            return Some(format!("deny {}", pattern.text));
+        }
+        if !self.allow.is_empty()
+            && !self
+                .allow
+                .iter()
+                .any(|pattern| pattern.matches(path, ignore_case))
+        {
+            return Some("no allow rule".to_string());
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_file_patterns() {
+        let cases = [
+            ("*.key", "server.key", true),
+            ("*.key", "boot/server.key", true),
+            ("*.key", "server.key.pub", false),
+            ("*.key", "keys.key/file", false),
+            (".*", ".bashrc", true),
+            (".*", "boot/.hidden", true),
+            (".*", "boot/kernel", false),
+            ("*~", "boot/config~", true),
+            ("*.sw?", "boot/.config.swp", true),
+            ("#*#", "#config#", true),
+            (".git/", ".git/config", true),
+            (".git/", "boot/.git/objects/ab", true),
+            (".git/", ".git", false),
+            (".git/", "boot/.gitignore", false),
+            ("dumps/*.core", "dumps/app.core", true),
+            ("/dumps/*.core", "dumps/app.core", true),
+            ("dumps/*.core", "dumps/app/x.core", false),
+            ("dumps/*.core", "other/dumps/app.core", false),
+            ("dumps/**.core", "dumps/app/x.core", true),
+            ("boot/**/*.key", "boot/a/b/c.key", true),
+            ("/boot/private/", "boot/private/key", true),
+            ("/boot/private/", "boot/private", false),
+            ("/boot/private/", "boot/public/private/key", false),
+            ("kernel-[0-9]*", "kernel-6.1", true),
+            ("kernel-[0-9]*", "kernel-rc", false),
+            ("kernel-[!0-9]*", "kernel-rc", true),
+            ("[a-]", "-", true),
+            ("?", "/", false),
+            ("*", "file", true),
+            ("**", "any/path", true),
+        ];
+
+        for (pattern, path, expected) in cases {
+            assert_eq!(
+                Pattern::new(pattern).unwrap().matches(path, false),
+                expected,
+                "{pattern} matches {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_ignoring_case() {
+        let pattern = Pattern::new("*.key").unwrap();
+        assert!(!pattern.matches("SERVER.KEY", false));
+        assert!(pattern.matches("SERVER.KEY", true));
+
+        let pattern = Pattern::new("[a-z]*").unwrap();
+        assert!(pattern.matches("Kernel", true));
+        assert!(!pattern.matches("Kernel", false));
+    }
+
+    #[test]
+    fn returns_error_on_invalid_pattern() {
+        for pattern in ["", "/", "[abc", "file[", "[]"] {
+            assert!(Pattern::new(pattern).is_err(), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn denies_by_rules() {
+        let rules = Rules::new(&FileFilter {
+            allow: vec!["dumps/*.core".to_string()],
+            deny: vec!["*secret*".to_string()],
+        })
+        .unwrap();
+
+        assert_eq!(rules.denied_by("dumps/app.core", false), None);
+        assert_eq!(
+            rules.denied_by("dumps/secret.core", false).as_deref(),
+            Some("deny *secret*")
+        );
+        assert_eq!(
+            rules.denied_by("boot/kernel", false).as_deref(),
+            Some("no allow rule")
+        );
+        assert_eq!(Rules::default().denied_by("boot/kernel", false), None);
+    }
+}
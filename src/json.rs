@@ -0,0 +1,136 @@
+use crate::toml::{Table, Value};
+
+/// Writes the tables of a configuration file as a JSON object. Entries
+/// before any header become members of the object, `[name]` tables become
+/// nested objects and `[[name]]` tables become arrays of objects.
+pub(crate) fn write(tables: &[Table]) -> String {
+    let mut members = Vec::new();
+
+    for (index, table) in tables.iter().enumerate() {
+        if table.name.is_empty() {
+            members.extend(
+                table
+                    .entries
+                    .iter()
+                    .map(|entry| (entry.key.clone(), write_value(&entry.value))),
+            );
+        } else if !table.array {
+            members.push((table.name.clone(), write_table(table, 1)));
+        } else if tables[..index]
+            .iter()
+            .all(|previous| previous.name != table.name)
+        {
+            let elements = tables
+                .iter()
+                .filter(|other| other.array && other.name == table.name)
+                .map(|other| format!("    {}", write_table(other, 2)))
+                .collect::<Vec<_>>();
+            members.push((
+                table.name.clone(),
+                format!("[\n{}\n  ]", elements.join(",\n")),
+            ));
+        }
+    }
+
+    write_object(&members, 0)
+}
+
+fn write_table(table: &Table, depth: usize) -> String {
+    let members = table
+        .entries
+        .iter()
+        .map(|entry| (entry.key.clone(), write_value(&entry.value)))
+        .collect::<Vec<_>>();
+
+    write_object(&members, depth)
+}
+
+fn write_object(members: &[(String, String)], depth: usize) -> String {
+    if members.is_empty() {
+        return "{}".to_string();
+    }
+
+    let indent = "  ".repeat(depth + 1);
+    let members = members
+        .iter()
+        .map(|(key, value)| format!("{indent}{}: {value}", write_string(key)))
+        .collect::<Vec<_>>();
+
+    format!("{{\n{}\n{}}}", members.join(",\n"), "  ".repeat(depth))
+}
+
+fn write_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => write_string(value),
+        Value::Integer(value) => value.to_string(),
+        Value::Boolean(value) => value.to_string(),
+        Value::Array(values) => format!(
+            "[{}]",
+            values
+                .iter()
+                .map(write_value)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn write_string(value: &str) -> String {
+    let mut string = String::with_capacity(value.len() + 2);
+    string.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            '\t' => string.push_str("\\t"),
+            c if c.is_control() => string.push_str(&format!("\\u{:04x}", c as u32)),
+            c => string.push(c),
+        }
+    }
+    string.push('"');
+
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toml::Entry;
+
+    #[test]
+    fn writes_tables_as_object() {
+        let mut root = Table::new("", false);
+        root.entries.push(Entry::new("directory", "/srv/\"tftp\""));
+        root.entries.push(Entry::new("read_only", true));
+        let mut limits = Table::new("limits", false);
+        limits.entries.push(Entry::new("max_block_size", 1468_i64));
+        let mut first = Table::new("mount", true);
+        first.entries.push(Entry::new("prefix", "boot"));
+        let mut second = Table::new("mount", true);
+        second.entries.push(Entry::new(
+            "tags",
+            vec![Value::from("a"), Value::from(1_i64)],
+        ));
+
+        assert_eq!(
+            write(&[root, limits, first, Table::new("retry", false), second]),
+            r#"{
+  "directory": "/srv/\"tftp\"",
+  "read_only": true,
+  "limits": {
+    "max_block_size": 1468
+  },
+  "mount": [
+    {
+      "prefix": "boot"
+    },
+    {
+      "tags": ["a", 1]
+    }
+  ],
+  "retry": {}
+}"#
+        );
+    }
+}
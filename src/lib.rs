@@ -15,23 +15,52 @@
 //! Since TFTP servers do not offer any type of login or access control mechanisms, this server only allows
 //! transfer and receiving inside a chosen folder, and disallows external file access.
 
+mod cidr;
 mod config;
 mod convert;
+mod glob;
+mod json;
+mod normalize;
+mod overwrite;
 mod packet;
+mod policy;
+mod quota;
+mod ratelimit;
 mod server;
 mod socket;
+mod stats;
+mod sys;
+mod toml;
+mod upload;
 mod window;
 mod worker;
 
+pub use cidr::Cidr;
+pub use config::AccessList;
 pub use config::Config;
+pub use config::FileFilter;
+pub use config::Listener;
+pub use config::Mount;
+pub use config::OptionLimits;
+pub use config::OverwritePolicy;
+pub use config::Profile;
+pub use config::Quotas;
+pub use config::RateLimit;
+pub use config::RateLimits;
+pub use config::RetryPolicy;
+pub use config::SymlinkPolicy;
 pub use convert::Convert;
 pub use packet::ErrorCode;
 pub use packet::Opcode;
 pub use packet::OptionType;
 pub use packet::Packet;
 pub use packet::TransferOption;
+pub use server::ConfigSource;
+pub use server::RequestHook;
 pub use server::Server;
+pub use server::ServerBuilder;
 pub use socket::ServerSocket;
 pub use socket::Socket;
+pub use stats::Stats;
 pub use window::Window;
 pub use worker::Worker;
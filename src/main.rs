@@ -1,26 +1,81 @@
 use std::{env, process};
-use tftpd::{Config, Server};
+use tftpd::{Config, ServerBuilder};
 
 fn main() {
-    let config = Config::new(env::args()).unwrap_or_else(|err| {
+    let mut args = env::args().collect::<Vec<_>>();
+    if args.get(1).is_some_and(|arg| arg == "check-config") {
+        args.remove(1);
+        check_config(args);
+    }
+
+    let config = Config::new(args.clone().into_iter()).unwrap_or_else(|err| {
         eprintln!("Problem parsing arguments: {err}");
         process::exit(1)
     });
 
-    let mut server = Server::new(&config).unwrap_or_else(|err| {
-        eprintln!(
-            "Problem creating server on {}:{}: {err}",
-            config.ip_address, config.port
-        );
-        process::exit(1)
-    });
+    let mut server = ServerBuilder::from_config(config.clone())
+        .config_source(move || Config::new(args.clone().into_iter()))
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("Problem creating server: {err}");
+            process::exit(1)
+        });
+    if config.socket_activation {
+        // The variables are meant for this process only, not for its children.
+        // No other threads are running yet.
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+    server.remove_interrupted_uploads();
 
+    let addresses = server
+        .local_addrs()
+        .unwrap_or_default()
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
     println!(
-        "Running TFTP Server on {}:{} in {}",
-        config.ip_address,
-        config.port,
+        "Running TFTP Server on {addresses} in {}",
         config.directory.display()
     );
 
     server.listen();
 }
+
+/// Validates the configuration and prints it without starting the server,
+/// exiting with a non-zero status if there are any problems.
+fn check_config(mut args: Vec<String>) -> ! {
+    let format = match args.iter().position(|arg| arg == "--format") {
+        Some(index) if index + 1 < args.len() => {
+            args.remove(index);
+            args.remove(index)
+        }
+        Some(_) => {
+            eprintln!("Problem parsing arguments: Missing format after flag");
+            process::exit(1)
+        }
+        None => "toml".to_string(),
+    };
+
+    let config = Config::new(args.into_iter()).unwrap_or_else(|err| {
+        eprintln!("Problem parsing arguments: {err}");
+        process::exit(1)
+    });
+
+    match format.as_str() {
+        "toml" => print!("{}", config.to_toml()),
+        "json" => println!("{}", config.to_json()),
+        invalid => {
+            eprintln!("Problem parsing arguments: Invalid format: {invalid}");
+            process::exit(1)
+        }
+    }
+
+    let problems = config.check();
+    for problem in &problems {
+        eprintln!("Problem in configuration: {problem}");
+    }
+    process::exit(if problems.is_empty() { 0 } else { 1 })
+}
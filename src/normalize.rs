@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Normalizes the file name of a request into a path relative to the served
+/// directory, with segments separated by `/`.
+///
+/// Both `/` and `\` separate segments, and empty and `.` segments are
+/// dropped. A `..` segment removes the segment before it, and the file name
+/// is refused if it would leave the served directory this way. If the
+/// normalized path starts with the segments of `prefix`, such as
+/// `/tftpboot/`, they are removed, optionally ignoring ASCII case. File
+/// names that normalize to the served directory itself are refused as well.
+pub(crate) fn normalize(filename: &str, prefix: &str, ignore_case: bool) -> Option<String> {
+    let mut segments = split_segments(filename)?;
+    let prefix = split_segments(prefix).unwrap_or_default();
+    let matches = |(segment, prefix): (&&str, &&str)| {
+        segment == prefix || ignore_case && segment.eq_ignore_ascii_case(prefix)
+    };
+    if segments.len() >= prefix.len() && segments.iter().zip(&prefix).all(matches) {
+        segments.drain(..prefix.len());
+    }
+
+    if segments.is_empty() {
+        return None;
+    }
+
+    Some(segments.join("/"))
+}
+
+/// Joins a normalized `path` to `directory`, using the existing entries that
+/// match its segments when ignoring ASCII case. Segments without a matching
+/// entry are joined as they are, so that new files keep the requested name.
+pub(crate) fn resolve_case(directory: &Path, path: &str) -> PathBuf {
+    let mut resolved = directory.to_path_buf();
+    let mut segments = path.split('/');
+
+    while let Some(segment) = segments.next() {
+        let exact = resolved.join(segment);
+        if exact.symlink_metadata().is_ok() {
+            resolved = exact;
+            continue;
+        }
+
+        let entry = fs::read_dir(&resolved).ok().and_then(|entries| {
+            entries.flatten().find(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(segment))
+            })
+        });
+        match entry {
+            Some(entry) => resolved = entry.path(),
+            None => {
+                resolved = exact;
+                resolved.extend(segments);
+                break;
+            }
+        }
+    }
+
+    resolved
+}
+
+fn split_segments(path: &str) -> Option<Vec<&str>> {
+    let mut segments = Vec::new();
+
+    for segment in path.split(['/', '\\']) {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    Some(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn normalizes_file_names() {
+        let cases = [
+            ("kernel", "", Some("kernel")),
+            ("/kernel", "", Some("kernel")),
+            ("\\kernel", "", Some("kernel")),
+            ("boot/kernel", "", Some("boot/kernel")),
+            ("boot\\efi\\grubx64.efi", "", Some("boot/efi/grubx64.efi")),
+            ("//boot///kernel", "", Some("boot/kernel")),
+            ("./boot/./kernel", "", Some("boot/kernel")),
+            ("kernel..v2", "", Some("kernel..v2")),
+            ("..kernel", "", Some("..kernel")),
+            ("boot/../kernel", "", Some("kernel")),
+            ("boot\\..\\kernel", "", Some("kernel")),
+            ("boot/efi/../../kernel", "", Some("kernel")),
+            ("../kernel", "", None),
+            ("..\\kernel", "", None),
+            ("/..", "", None),
+            ("boot/../../kernel", "", None),
+            ("boot/efi/../../../kernel", "", None),
+            ("", "", None),
+            ("/", "", None),
+            ("boot/..", "", None),
+            ("/tftpboot/pxelinux.0", "/tftpboot/", Some("pxelinux.0")),
+            ("tftpboot/pxelinux.0", "/tftpboot", Some("pxelinux.0")),
+            ("\\tftpboot\\pxelinux.0", "tftpboot", Some("pxelinux.0")),
+            ("/tftpboot/boot/kernel", "/tftpboot/boot", Some("kernel")),
+            (
+                "/tftpbootx/pxelinux.0",
+                "/tftpboot",
+                Some("tftpbootx/pxelinux.0"),
+            ),
+            (
+                "/srv/tftpboot/kernel",
+                "/tftpboot",
+                Some("srv/tftpboot/kernel"),
+            ),
+            ("pxelinux.0", "/tftpboot", Some("pxelinux.0")),
+            ("/tftpboot", "/tftpboot", None),
+            ("/tftpboot/../tftpboot/kernel", "/tftpboot", Some("kernel")),
+            ("/tftpboot/../../kernel", "/tftpboot", None),
+        ];
+
+        for (filename, prefix, expected) in cases {
+            assert_eq!(
+                normalize(filename, prefix, false).as_deref(),
+                expected,
+                "{filename} without {prefix}"
+            );
+        }
+
+        assert_eq!(
+            normalize("/TFTPBOOT/PXELINUX.0", "/tftpboot", false).as_deref(),
+            Some("TFTPBOOT/PXELINUX.0")
+        );
+        assert_eq!(
+            normalize("/TFTPBOOT/PXELINUX.0", "/tftpboot", true).as_deref(),
+            Some("PXELINUX.0")
+        );
+    }
+
+    #[test]
+    fn resolves_case_of_existing_entries() {
+        let root = env::temp_dir().join(format!("tftpd-case-{}", std::process::id()));
+        fs::create_dir_all(root.join("Boot/EFI")).unwrap();
+        fs::write(root.join("Boot/EFI/grubx64.efi"), "grub").unwrap();
+
+        let cases = [
+            ("Boot/EFI/grubx64.efi", "Boot/EFI/grubx64.efi"),
+            ("BOOT/EFI/GRUBX64.EFI", "Boot/EFI/grubx64.efi"),
+            ("boot/efi/grubx64.efi", "Boot/EFI/grubx64.efi"),
+            ("boot/efi/new.efi", "Boot/EFI/new.efi"),
+            ("boot/new/file", "Boot/new/file"),
+            ("other/file", "other/file"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(resolve_case(&root, path), root.join(expected), "{path}");
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use crate::OverwritePolicy;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of digits the timestamps of kept versions are padded to, so that
+/// other files with numbered names are never taken for versions and removed.
+const TIMESTAMP_DIGITS: usize = 10;
+
+/// Returns whether an upload may replace the existing `file`.
+pub(crate) fn allows(file: &Path, overwrite: OverwritePolicy) -> bool {
+    match overwrite {
+        OverwritePolicy::Never => false,
+        OverwritePolicy::Always | OverwritePolicy::KeepVersions(_) => true,
+        OverwritePolicy::OlderThan(age) => file
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().is_ok_and(|elapsed| elapsed >= age)),
+    }
+}
+
+/// Moves a finished upload from `temp` to `file`. Unless the policy allows
+/// replacing files, this fails if `file` was created since the request was
+/// checked. When the policy keeps versions, an existing file is renamed to
+/// `NAME.TIMESTAMP` first. A symbolic link at `file` is replaced
+/// instead of written through.
+pub(crate) fn replace(temp: &Path, file: &Path, overwrite: OverwritePolicy) -> io::Result<()> {
+    match overwrite {
+        OverwritePolicy::Never => match fs::hard_link(temp, file) {
+            // Unlike a rename, a hard link never replaces an existing file.
+            Ok(()) => fs::remove_file(temp),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(err),
+            // Some file systems have no hard links.
+            Err(_) if file.symlink_metadata().is_ok() => Err(io::ErrorKind::AlreadyExists.into()),
+            Err(_) => fs::rename(temp, file),
+        },
+        OverwritePolicy::KeepVersions(keep) => {
+            keep_version(file, keep)?;
+            fs::rename(temp, file)
+        }
+        OverwritePolicy::Always | OverwritePolicy::OlderThan(_) => fs::rename(temp, file),
+    }
+}
+
+/// Renames an existing `file` to `NAME.TIMESTAMP` and removes the
+/// oldest versions beyond the `keep` newest ones.
+fn keep_version(file: &Path, keep: usize) -> io::Result<()> {
+    if file.symlink_metadata().is_err() {
+        return Ok(());
+    }
+
+    let mut timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let mut version = version_path(file, timestamp);
+    // Uploads within the same second get the next free timestamp, so that
+    // versions still sort by age.
+    while version.symlink_metadata().is_ok() {
+        timestamp += 1;
+        version = version_path(file, timestamp);
+    }
+    fs::rename(file, version)?;
+
+    let mut versions = versions(file)?;
+    versions.sort();
+    let excess = versions.len().saturating_sub(keep);
+    for (_, version) in versions.into_iter().take(excess) {
+        fs::remove_file(version)?;
+    }
+
+    Ok(())
+}
+
+fn version_path(file: &Path, timestamp: u64) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{timestamp:0TIMESTAMP_DIGITS$}"));
+    file.with_file_name(name)
+}
+
+/// Returns the kept versions of `file` along with their timestamps.
+fn versions(file: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let (Some(directory), Some(name)) = (file.parent(), file.file_name()) else {
+        return Ok(Vec::new());
+    };
+    let Some(name) = name.to_str() else {
+        return Ok(Vec::new());
+    };
+
+    let mut versions = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let timestamp = entry
+            .file_name()
+            .to_str()
+            .and_then(|entry_name| entry_name.strip_prefix(name)?.strip_prefix('.'))
+            .filter(|suffix| {
+                suffix.len() == TIMESTAMP_DIGITS && suffix.bytes().all(|b| b.is_ascii_digit())
+            })
+            .and_then(|suffix| suffix.parse().ok());
+        if let Some(timestamp) = timestamp {
+            versions.push((timestamp, entry.path()));
+        }
+    }
+
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tftpd-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn allows_overwriting_by_policy() {
+        let dir = temp_dir("overwrite-allows");
+        let file = dir.join("log");
+        fs::write(&file, "old").unwrap();
+
+        let cases = [
+            (OverwritePolicy::Never, false),
+            (OverwritePolicy::Always, true),
+            (OverwritePolicy::KeepVersions(3), true),
+            (OverwritePolicy::OlderThan(Duration::ZERO), true),
+            (OverwritePolicy::OlderThan(Duration::from_secs(3600)), false),
+        ];
+        for (overwrite, expected) in cases {
+            assert_eq!(allows(&file, overwrite), expected, "{overwrite}");
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_limited_versions() {
+        let dir = temp_dir("overwrite-versions");
+        let file = dir.join("device.log");
+        let temp = dir.join(".device.log.part");
+        fs::write(dir.join("device.log.bak"), "unrelated").unwrap();
+        fs::write(dir.join("device.log.1"), "unrelated").unwrap();
+        fs::write(dir.join("device.log.0000000001"), "oldest").unwrap();
+        fs::write(&file, "first").unwrap();
+
+        for content in ["second", "third", "fourth"] {
+            fs::write(&temp, content).unwrap();
+            replace(&temp, &file, OverwritePolicy::KeepVersions(2)).unwrap();
+            assert_eq!(fs::read_to_string(&file).unwrap(), content);
+            assert!(!temp.exists());
+        }
+
+        let mut versions = versions(&file).unwrap();
+        versions.sort();
+        let contents = versions
+            .iter()
+            .map(|(_, path)| fs::read_to_string(path).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, vec!["second", "third"]);
+        assert!(dir.join("device.log.bak").exists());
+        assert!(dir.join("device.log.1").exists());
+        assert!(!dir.join("device.log.0000000001").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replaces_files_by_policy() {
+        let dir = temp_dir("overwrite-replace");
+        let file = dir.join("log");
+        let temp = dir.join(".log.part");
+
+        fs::write(&temp, "first").unwrap();
+        replace(&temp, &file, OverwritePolicy::Never).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "first");
+        assert!(!temp.exists());
+
+        fs::write(&temp, "second").unwrap();
+        let err = replace(&temp, &file, OverwritePolicy::Never).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "first");
+
+        replace(&temp, &file, OverwritePolicy::Always).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "second");
+        assert!(!temp.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -95,7 +95,7 @@ impl Packet {
 /// assert_eq!(Opcode::Ack.as_bytes(), [0x00, 0x04]);
 /// ```
 #[repr(u16)]
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Opcode {
     /// Read request opcode
     Rrq = 0x0001,
@@ -0,0 +1,868 @@
+use crate::glob::Rules;
+use crate::normalize;
+use crate::sys;
+use crate::{
+    AccessList, Cidr, Config, Mount, OptionLimits, OverwritePolicy, Quotas, RateLimits,
+    RetryPolicy, SymlinkPolicy,
+};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+const MIN_BLOCK_SIZE: usize = 8;
+
+/// The settings that decide how new requests are handled. Unlike the
+/// sockets of a [`Server`](crate::Server), they can be replaced while the
+/// server is running, which leaves running transfers untouched.
+pub(crate) struct Policy {
+    pub(crate) listeners: Vec<ListenerPolicy>,
+    pub(crate) profiles: Vec<ProfilePolicy>,
+    pub(crate) mounts: Vec<MountPolicy>,
+    pub(crate) limits: OptionLimits,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) strip_prefix: String,
+    pub(crate) case_insensitive: bool,
+    pub(crate) read_access: AccessList,
+    pub(crate) write_access: AccessList,
+    pub(crate) drop_denied: bool,
+    pub(crate) read_files: Rules,
+    pub(crate) write_files: Rules,
+    pub(crate) overwrite: OverwritePolicy,
+    pub(crate) create_dirs: bool,
+    pub(crate) attributes: Attributes,
+    pub(crate) quotas: Quotas,
+    pub(crate) rate_limits: RateLimits,
+    pub(crate) port_range: Option<RangeInclusive<u16>>,
+}
+
+/// The settings of a single listener, in the same order as the sockets of
+/// the server.
+#[derive(Clone)]
+pub(crate) struct ListenerPolicy {
+    pub(crate) directory: PathBuf,
+    pub(crate) read_only: bool,
+    pub(crate) write_only: bool,
+}
+
+/// The settings for the clients of a [`Profile`](crate::Profile), with the
+/// limits and retry policy taken from the config when not set.
+pub(crate) struct ProfilePolicy {
+    pub(crate) clients: Vec<Cidr>,
+    pub(crate) directory: Option<PathBuf>,
+    pub(crate) read_only: Option<bool>,
+    pub(crate) write_only: Option<bool>,
+    pub(crate) limits: OptionLimits,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) attributes: Attributes,
+}
+
+/// A [`Mount`] along with the attributes of the files uploaded below it.
+pub(crate) struct MountPolicy {
+    pub(crate) mount: Mount,
+    pub(crate) attributes: Attributes,
+}
+
+/// The modes and owner given to uploaded files and the directories created
+/// for them, with the user and group looked up. Attributes that are not set
+/// are left to the umask and the user of the process.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Attributes {
+    pub(crate) file_mode: Option<u32>,
+    pub(crate) dir_mode: Option<u32>,
+    pub(crate) owner: Option<u32>,
+    pub(crate) group: Option<u32>,
+}
+
+/// The settings a single request is handled with. The requested file is
+/// only served if it is inside of `directory`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct RequestPolicy {
+    pub(crate) name: String,
+    pub(crate) directory: PathBuf,
+    pub(crate) file: PathBuf,
+    pub(crate) read_only: bool,
+    pub(crate) write_only: bool,
+    pub(crate) limits: OptionLimits,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) case_insensitive: bool,
+    pub(crate) attributes: Attributes,
+}
+
+impl Policy {
+    /// Validates the supplied [`Config`] and creates the [`Policy`] for
+    /// `sockets` already bound sockets followed by the listeners of the
+    /// config.
+    pub(crate) fn new(config: &Config, sockets: usize) -> Result<Policy, Box<dyn Error>> {
+        if !config.directory.is_dir() {
+            return Err(format!("{} is not a directory", config.directory.display()).into());
+        }
+        check_limits(&config.limits)?;
+        check_rate_limits(&config.rate_limits)?;
+        if config.overwrite == OverwritePolicy::KeepVersions(0) {
+            return Err("At least one version must be kept".into());
+        }
+        if config.quotas.min_free_space.is_some() {
+            sys::free_space(&config.directory)
+                .map_err(|err| format!("Cannot check free space: {err}"))?;
+        }
+
+        if config.read_only && config.write_only {
+            return Err("The server cannot be both read-only and write-only".into());
+        }
+        let strip_prefix = config.strip_prefix.clone().unwrap_or_default();
+        if strip_prefix
+            .split(['/', '\\'])
+            .any(|segment| segment == "..")
+        {
+            return Err(format!("Prefix {strip_prefix} must not contain ..").into());
+        }
+
+        let default = ListenerPolicy {
+            directory: config.directory.clone(),
+            read_only: config.read_only,
+            write_only: config.write_only,
+        };
+        let mut listeners = vec![default.clone(); sockets];
+        for listener in &config.listeners {
+            let directory = listener
+                .directory
+                .clone()
+                .unwrap_or(config.directory.clone());
+            if !directory.is_dir() {
+                return Err(format!("{} is not a directory", directory.display()).into());
+            }
+
+            listeners.push(ListenerPolicy {
+                directory,
+                read_only: listener.read_only.unwrap_or(config.read_only),
+                write_only: listener.write_only.unwrap_or(config.write_only),
+            });
+        }
+        if listeners.is_empty() {
+            listeners.push(default);
+        }
+
+        let mut profiles = Vec::with_capacity(config.profiles.len());
+        for profile in &config.profiles {
+            if profile.clients.is_empty() {
+                return Err("Profiles must have at least one client network".into());
+            }
+            if let Some(directory) = &profile.directory {
+                if !directory.is_dir() {
+                    return Err(format!("{} is not a directory", directory.display()).into());
+                }
+            }
+            let limits = profile.limits.unwrap_or(config.limits);
+            check_limits(&limits)?;
+
+            profiles.push(ProfilePolicy {
+                clients: profile.clients.clone(),
+                directory: profile.directory.clone(),
+                read_only: profile.read_only,
+                write_only: profile.write_only,
+                limits,
+                retry: profile.retry.unwrap_or(config.retry),
+                attributes: Attributes::new(
+                    profile.file_mode,
+                    profile.dir_mode,
+                    profile.file_owner.as_deref(),
+                    profile.file_group.as_deref(),
+                )?,
+            });
+        }
+
+        let mut mounts = Vec::with_capacity(config.mounts.len());
+        for mount in &config.mounts {
+            if mount.prefix.is_empty() {
+                return Err("Mount prefixes must not be empty".into());
+            }
+            if mounts
+                .iter()
+                .any(|other: &MountPolicy| other.mount.prefix == mount.prefix)
+            {
+                return Err(format!("{} is mounted more than once", mount.prefix).into());
+            }
+            if !mount.directory.is_dir() {
+                return Err(format!("{} is not a directory", mount.directory.display()).into());
+            }
+
+            mounts.push(MountPolicy {
+                mount: mount.clone(),
+                attributes: Attributes::new(
+                    mount.file_mode,
+                    mount.dir_mode,
+                    mount.file_owner.as_deref(),
+                    mount.file_group.as_deref(),
+                )?,
+            });
+        }
+
+        Ok(Policy {
+            listeners,
+            profiles,
+            mounts,
+            limits: config.limits,
+            retry: config.retry,
+            symlinks: config.symlinks,
+            strip_prefix,
+            case_insensitive: config.case_insensitive,
+            read_access: config.read_access.clone(),
+            write_access: config.write_access.clone(),
+            drop_denied: config.drop_denied,
+            read_files: Rules::new(&config.read_files)?,
+            write_files: Rules::new(&config.write_files)?,
+            overwrite: config.overwrite,
+            create_dirs: config.create_dirs,
+            attributes: Attributes::new(
+                config.file_mode,
+                Some(config.dir_mode),
+                config.file_owner.as_deref(),
+                config.file_group.as_deref(),
+            )?,
+            quotas: config.quotas,
+            rate_limits: config.rate_limits,
+            port_range: config.port_range.clone(),
+        })
+    }
+
+    /// Returns the settings for a request for `filename` from `client` that
+    /// arrived on `listener`, or [`None`] if the file name leaves the served
+    /// directory. The first profile that matches the client and the mount
+    /// with the longest prefix matching the normalized file name are used.
+    pub(crate) fn for_request(
+        &self,
+        listener: usize,
+        client: &IpAddr,
+        filename: &str,
+    ) -> Option<RequestPolicy> {
+        let filename = normalize::normalize(filename, &self.strip_prefix, self.case_insensitive)?;
+        let listener = &self.listeners[listener];
+        let profile = self
+            .profiles
+            .iter()
+            .find(|profile| profile.clients.iter().any(|cidr| cidr.contains(client)));
+
+        let mut policy = match profile {
+            Some(profile) => RequestPolicy {
+                directory: profile
+                    .directory
+                    .clone()
+                    .unwrap_or(listener.directory.clone()),
+                name: String::new(),
+                file: PathBuf::new(),
+                read_only: profile.read_only.unwrap_or(listener.read_only),
+                write_only: profile.write_only.unwrap_or(listener.write_only),
+                limits: profile.limits,
+                retry: profile.retry,
+                symlinks: self.symlinks,
+                case_insensitive: self.case_insensitive,
+                attributes: profile.attributes.or(self.attributes),
+            },
+            None => RequestPolicy {
+                directory: listener.directory.clone(),
+                name: String::new(),
+                file: PathBuf::new(),
+                read_only: listener.read_only,
+                write_only: listener.write_only,
+                limits: self.limits,
+                retry: self.retry,
+                symlinks: self.symlinks,
+                case_insensitive: self.case_insensitive,
+                attributes: self.attributes,
+            },
+        };
+
+        let mount = self
+            .mounts
+            .iter()
+            .filter_map(|mount| {
+                let rest = mount.mount.strip_prefix(&filename, self.case_insensitive)?;
+                Some((mount, rest))
+            })
+            .max_by_key(|(mount, _)| mount.mount.prefix.len());
+        let rest = match mount {
+            Some((MountPolicy { mount, attributes }, rest)) => {
+                policy.directory = mount.directory.clone();
+                policy.read_only |= mount.read_only;
+                policy.write_only |= mount.write_only;
+                policy.attributes = attributes.or(policy.attributes);
+                rest
+            }
+            None => &filename,
+        };
+        policy.file = if self.case_insensitive {
+            normalize::resolve_case(&policy.directory, rest)
+        } else {
+            policy.directory.join(rest)
+        };
+        policy.name = filename;
+
+        Some(policy)
+    }
+
+    /// Returns every directory some client may upload files to, each only
+    /// once.
+    pub(crate) fn writable_directories(&self) -> Vec<&Path> {
+        let all_read_only = self.listeners.iter().all(|listener| listener.read_only);
+        // Profiles without a directory of their own write to the listener's.
+        let profile_writes = self
+            .profiles
+            .iter()
+            .any(|profile| profile.directory.is_none() && profile.read_only == Some(false));
+        let any_writes = !all_read_only
+            || self
+                .profiles
+                .iter()
+                .any(|profile| profile.read_only == Some(false));
+
+        let mut directories: Vec<&Path> = Vec::new();
+        let all = self
+            .listeners
+            .iter()
+            .filter(|listener| !listener.read_only || profile_writes)
+            .map(|listener| listener.directory.as_path())
+            .chain(self.profiles.iter().filter_map(|profile| {
+                match profile.read_only.unwrap_or(all_read_only) {
+                    true => None,
+                    false => profile.directory.as_deref(),
+                }
+            }))
+            .chain(
+                self.mounts
+                    .iter()
+                    .filter(|mount| any_writes && !mount.mount.read_only)
+                    .map(|mount| mount.mount.directory.as_path()),
+            );
+        for directory in all {
+            if !directories.contains(&directory) {
+                directories.push(directory);
+            }
+        }
+
+        directories
+    }
+
+    /// Moves every directory to where it is found after changing
+    /// the root directory of the process to `root`.
+    pub(crate) fn chroot(&mut self, root: &Path) -> Result<(), Box<dyn Error>> {
+        for listener in &mut self.listeners {
+            listener.directory = chrooted_path(&listener.directory, root)?;
+        }
+        for profile in &mut self.profiles {
+            if let Some(directory) = &mut profile.directory {
+                *directory = chrooted_path(directory, root)?;
+            }
+        }
+        for MountPolicy { mount, .. } in &mut self.mounts {
+            mount.directory = chrooted_path(&mount.directory, root)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Attributes {
+    /// Creates the attributes from the settings of a config, profile or
+    /// mount, looking up the owner and group.
+    fn new(
+        file_mode: Option<u32>,
+        dir_mode: Option<u32>,
+        owner: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<Attributes, Box<dyn Error>> {
+        let owner = owner
+            .map(|owner| match owner.parse() {
+                Ok(uid) => Ok(uid),
+                Err(_) => sys::lookup_user(owner).map(|(uid, _)| uid),
+            })
+            .transpose()
+            .map_err(|err| format!("Cannot look up file owner: {err}"))?;
+        let group = group
+            .map(sys::lookup_group)
+            .transpose()
+            .map_err(|err| format!("Cannot look up file group: {err}"))?;
+
+        Ok(Attributes {
+            file_mode,
+            dir_mode,
+            owner,
+            group,
+        })
+    }
+
+    /// Returns these attributes, with the ones that are not set taken from
+    /// `fallback`.
+    pub(crate) fn or(self, fallback: Attributes) -> Attributes {
+        Attributes {
+            file_mode: self.file_mode.or(fallback.file_mode),
+            dir_mode: self.dir_mode.or(fallback.dir_mode),
+            owner: self.owner.or(fallback.owner),
+            group: self.group.or(fallback.group),
+        }
+    }
+
+    /// Applies the owner and file mode to an uploaded `file`.
+    pub(crate) fn apply_to_file(&self, file: &File) -> io::Result<()> {
+        if self.owner.is_some() || self.group.is_some() {
+            sys::fchown(file, self.owner, self.group)?;
+        }
+        // The mode is set last, since changing the owner clears the set-id
+        // bits.
+        if let Some(mode) = self.file_mode {
+            sys::set_file_mode(file, mode)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the owner and directory mode to a created `directory`.
+    pub(crate) fn apply_to_dir(&self, directory: &Path) -> io::Result<()> {
+        if self.owner.is_some() || self.group.is_some() {
+            sys::chown(directory, self.owner, self.group)?;
+        }
+        if let Some(mode) = self.dir_mode {
+            sys::set_mode(directory, mode)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check_limits(limits: &OptionLimits) -> Result<(), Box<dyn Error>> {
+    if limits.max_block_size < MIN_BLOCK_SIZE {
+        return Err(format!("Maximum block size must be at least {MIN_BLOCK_SIZE}").into());
+    }
+    if limits.max_window_size == 0 {
+        return Err("Maximum window size must be at least 1".into());
+    }
+
+    Ok(())
+}
+
+fn check_rate_limits(rate_limits: &RateLimits) -> Result<(), Box<dyn Error>> {
+    for limit in [rate_limits.client, rate_limits.global].iter().flatten() {
+        if limit.rate == 0 || limit.burst == 0 {
+            return Err("Rate limits must allow at least 1 request".into());
+        }
+    }
+    if rate_limits.ban_after == Some(0) {
+        return Err("Clients can only be banned after at least 1 request".into());
+    }
+
+    Ok(())
+}
+
+/// Rewrites the directories of a [`Config`] read after the process was
+/// confined to its directory, so that they point inside the chroot.
+pub(crate) fn rebase_config(config: &mut Config) -> Result<(), Box<dyn Error>> {
+    let root = config.directory.clone();
+    config.directory = PathBuf::from("/");
+
+    let directories = config
+        .listeners
+        .iter_mut()
+        .filter_map(|listener| listener.directory.as_mut())
+        .chain(
+            config
+                .profiles
+                .iter_mut()
+                .filter_map(|profile| profile.directory.as_mut()),
+        )
+        .chain(config.mounts.iter_mut().map(|mount| &mut mount.directory));
+    for directory in directories {
+        let relative = directory.strip_prefix(&root).map_err(|_| {
+            format!(
+                "{} is outside of the chroot directory {}",
+                directory.display(),
+                root.display()
+            )
+        })?;
+        *directory = Path::new("/").join(relative);
+    }
+
+    Ok(())
+}
+
+/// Returns where `directory` is found after changing the root directory of
+/// the process to `root`.
+fn chrooted_path(directory: &Path, root: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let directory = directory.canonicalize()?;
+    let relative = directory.strip_prefix(root).map_err(|_| {
+        format!(
+            "{} is outside of the chroot directory {}",
+            directory.display(),
+            root.display()
+        )
+    })?;
+
+    Ok(Path::new("/").join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileFilter, Listener, Profile};
+    use std::net::SocketAddr;
+    use std::{env, fs};
+
+    #[test]
+    fn creates_policy_for_sockets_and_listeners() {
+        let config = Config {
+            read_only: true,
+            listeners: vec![Listener {
+                address: SocketAddr::from(([127, 0, 0, 1], 0)),
+                directory: Some(PathBuf::from("src")),
+                read_only: Some(false),
+                write_only: None,
+            }],
+            ..Config::default()
+        };
+
+        let policy = Policy::new(&config, 1).unwrap();
+
+        assert_eq!(policy.listeners.len(), 2);
+        assert_eq!(policy.listeners[0].directory, config.directory);
+        assert!(policy.listeners[0].read_only);
+        assert_eq!(policy.listeners[1].directory, PathBuf::from("src"));
+        assert!(!policy.listeners[1].read_only);
+    }
+
+    #[test]
+    fn selects_profile_by_client_address() {
+        let config = Config {
+            read_only: true,
+            profiles: vec![
+                Profile {
+                    directory: Some(PathBuf::from("src")),
+                    limits: Some(OptionLimits {
+                        max_block_size: 8192,
+                        ..OptionLimits::default()
+                    }),
+                    ..Profile::new(vec!["10.1.0.0/16".parse().unwrap()])
+                },
+                Profile {
+                    read_only: Some(false),
+                    retry: Some(RetryPolicy {
+                        max_retries: 10,
+                        ..RetryPolicy::default()
+                    }),
+                    ..Profile::new(vec![
+                        "10.0.0.0/8".parse().unwrap(),
+                        "fd00::/8".parse().unwrap(),
+                    ])
+                },
+            ],
+            ..Config::default()
+        };
+        let policy = Policy::new(&config, 1).unwrap();
+
+        let lab = policy
+            .for_request(0, &"10.1.2.3".parse().unwrap(), "file")
+            .unwrap();
+        assert_eq!(lab.directory, PathBuf::from("src"));
+        assert!(lab.read_only);
+        assert_eq!(lab.limits.max_block_size, 8192);
+
+        for client in ["10.2.0.1", "::ffff:10.2.0.1", "fd00::1"] {
+            let farm = policy
+                .for_request(0, &client.parse().unwrap(), "file")
+                .unwrap();
+            assert_eq!(farm.directory, config.directory);
+            assert!(!farm.read_only);
+            assert_eq!(farm.limits, OptionLimits::default());
+            assert_eq!(farm.retry.max_retries, 10);
+        }
+
+        assert_eq!(
+            policy.for_request(0, &"192.168.1.1".parse().unwrap(), "file"),
+            Some(RequestPolicy {
+                name: "file".to_string(),
+                directory: config.directory.clone(),
+                file: config.directory.join("file"),
+                read_only: true,
+                write_only: false,
+                limits: OptionLimits::default(),
+                retry: RetryPolicy::default(),
+                symlinks: SymlinkPolicy::Inside,
+                case_insensitive: false,
+                attributes: Attributes {
+                    dir_mode: Some(0o755),
+                    ..Default::default()
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn lists_writable_directories() {
+        let config = Config {
+            directory: PathBuf::from("src"),
+            read_only: true,
+            profiles: vec![
+                Profile {
+                    directory: Some(PathBuf::from("/")),
+                    read_only: Some(false),
+                    ..Profile::new(vec!["10.0.0.0/8".parse().unwrap()])
+                },
+                Profile {
+                    directory: Some(PathBuf::from("target")),
+                    ..Profile::new(vec!["10.1.0.0/16".parse().unwrap()])
+                },
+            ],
+            mounts: vec![
+                Mount {
+                    read_only: true,
+                    ..Mount::new("boot", "src")
+                },
+                Mount::new("dumps", ".."),
+            ],
+            ..Config::default()
+        };
+        let policy = Policy::new(&config, 0).unwrap();
+        assert_eq!(
+            policy.writable_directories(),
+            vec![Path::new("/"), Path::new("..")]
+        );
+
+        // Without the writing profile, the mount inherits read-only access.
+        let config = Config {
+            profiles: Vec::new(),
+            ..config
+        };
+        let policy = Policy::new(&config, 0).unwrap();
+        assert!(policy.writable_directories().is_empty());
+    }
+
+    #[test]
+    fn resolves_longest_matching_mount() {
+        let config = Config {
+            mounts: vec![
+                Mount {
+                    read_only: true,
+                    ..Mount::new("boot", "src")
+                },
+                Mount::new("boot/fw", "/"),
+                Mount {
+                    write_only: true,
+                    ..Mount::new("dumps", "/")
+                },
+            ],
+            ..Config::default()
+        };
+        let policy = Policy::new(&config, 0).unwrap();
+        let client = "127.0.0.1".parse().unwrap();
+
+        let boot = policy.for_request(0, &client, "boot/kernel").unwrap();
+        assert_eq!(boot.directory, PathBuf::from("src"));
+        assert_eq!(boot.file, PathBuf::from("src/kernel"));
+        assert!(boot.read_only);
+
+        let firmware = policy
+            .for_request(0, &client, "/boot/fw/board.bin")
+            .unwrap();
+        assert_eq!(firmware.directory, PathBuf::from("/"));
+        assert_eq!(firmware.file, PathBuf::from("/board.bin"));
+        assert!(!firmware.read_only);
+
+        assert!(
+            policy
+                .for_request(0, &client, "dumps/core")
+                .unwrap()
+                .write_only
+        );
+
+        let unmounted = policy.for_request(0, &client, "bootloader").unwrap();
+        assert_eq!(unmounted.directory, config.directory);
+        assert_eq!(unmounted.file, config.directory.join("bootloader"));
+    }
+
+    #[test]
+    fn combines_upload_attributes() {
+        let config = Config {
+            file_mode: Some(0o600),
+            file_group: Some("0".to_string()),
+            profiles: vec![Profile {
+                file_mode: Some(0o640),
+                dir_mode: Some(0o750),
+                ..Profile::new(vec!["10.0.0.0/8".parse().unwrap()])
+            }],
+            mounts: vec![Mount {
+                file_mode: Some(0o644),
+                file_owner: Some("0".to_string()),
+                ..Mount::new("public", "/")
+            }],
+            ..Config::default()
+        };
+        let policy = Policy::new(&config, 0).unwrap();
+        let farm = "10.0.0.1".parse().unwrap();
+        let other = "192.168.1.1".parse().unwrap();
+
+        let cases = [
+            (&other, "file", Some(0o600), Some(0o755), None),
+            (&farm, "file", Some(0o640), Some(0o750), None),
+            (&other, "public/file", Some(0o644), Some(0o755), Some(0)),
+            (&farm, "public/file", Some(0o644), Some(0o750), Some(0)),
+        ];
+        for (client, file, file_mode, dir_mode, owner) in cases {
+            let request = policy.for_request(0, client, file).unwrap();
+            assert_eq!(
+                request.attributes,
+                Attributes {
+                    file_mode,
+                    dir_mode,
+                    owner,
+                    group: Some(0),
+                },
+                "{file} from {client}"
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_requested_file_names() {
+        let config = Config {
+            directory: PathBuf::from("."),
+            mounts: vec![Mount::new("boot", "src")],
+            strip_prefix: Some("/tftpboot/".to_string()),
+            ..Config::default()
+        };
+        let policy = Policy::new(&config, 0).unwrap();
+        let client = "127.0.0.1".parse().unwrap();
+
+        let file = |filename| Some(policy.for_request(0, &client, filename)?.file);
+        assert_eq!(
+            file("/tftpboot/boot/lib.rs"),
+            Some(PathBuf::from("src/lib.rs"))
+        );
+        assert_eq!(file("\\boot\\.\\lib.rs"), Some(PathBuf::from("src/lib.rs")));
+        assert_eq!(file("kernel..v2"), Some(PathBuf::from("./kernel..v2")));
+        assert_eq!(file("BOOT/LIB.RS"), Some(PathBuf::from("./BOOT/LIB.RS")));
+        assert_eq!(file("boot/../../Cargo.toml"), None);
+        assert_eq!(file("/tftpboot/"), None);
+
+        let policy = Policy::new(
+            &Config {
+                case_insensitive: true,
+                ..config
+            },
+            0,
+        )
+        .unwrap();
+        let boot = policy
+            .for_request(0, &client, "/TFTPBOOT/BOOT/LIB.RS")
+            .unwrap();
+        assert_eq!(boot.file, PathBuf::from("src/lib.rs"));
+    }
+
+    #[test]
+    fn rejects_invalid_policy() {
+        let mut config = Config::default();
+        config.limits.max_block_size = 4;
+        assert!(Policy::new(&config, 0).is_err());
+
+        let mut config = Config::default();
+        config.listeners.push(Listener {
+            address: SocketAddr::from(([127, 0, 0, 1], 0)),
+            directory: Some(PathBuf::from("/this/does/not/exist")),
+            read_only: None,
+            write_only: None,
+        });
+        assert!(Policy::new(&config, 0).is_err());
+
+        let config = Config {
+            profiles: vec![Profile::new(Vec::new())],
+            ..Config::default()
+        };
+        assert!(Policy::new(&config, 0).is_err());
+
+        let config = Config {
+            mounts: vec![Mount::new("boot", "/"), Mount::new("/boot/", "src")],
+            ..Config::default()
+        };
+        assert!(Policy::new(&config, 0).is_err());
+
+        let config = Config {
+            read_only: true,
+            write_only: true,
+            ..Config::default()
+        };
+        assert!(Policy::new(&config, 0).is_err());
+
+        let config = Config {
+            strip_prefix: Some("/tftpboot/..".to_string()),
+            ..Config::default()
+        };
+        assert!(Policy::new(&config, 0).is_err());
+
+        let config = Config {
+            read_files: FileFilter {
+                deny: vec!["[abc".to_string()],
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        assert!(Policy::new(&config, 0).is_err());
+
+        let config = Config {
+            rate_limits: RateLimits {
+                client: Some("0/10".parse().unwrap()),
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        assert!(Policy::new(&config, 0).is_err());
+
+        let config = Config {
+            overwrite: OverwritePolicy::KeepVersions(0),
+            ..Config::default()
+        };
+        assert!(Policy::new(&config, 0).is_err());
+
+        let config = Config {
+            mounts: vec![Mount {
+                file_owner: Some("no-such-tftpd-user".to_string()),
+                ..Mount::new("boot", "/")
+            }],
+            ..Config::default()
+        };
+        assert!(Policy::new(&config, 0).is_err());
+    }
+
+    #[test]
+    fn rebases_paths_inside_chroot() {
+        let root = env::temp_dir().join(format!("tftpd-chroot-{}", std::process::id()));
+        fs::create_dir_all(root.join("boot")).unwrap();
+
+        assert_eq!(chrooted_path(&root, &root).unwrap(), PathBuf::from("/"));
+        assert_eq!(
+            chrooted_path(&root.join("boot"), &root).unwrap(),
+            PathBuf::from("/boot")
+        );
+        assert!(chrooted_path(&env::temp_dir(), &root).is_err());
+
+        let mut config = Config {
+            directory: PathBuf::from("/srv/tftp"),
+            listeners: vec![Listener {
+                address: SocketAddr::from(([127, 0, 0, 1], 0)),
+                directory: Some(PathBuf::from("/srv/tftp/boot")),
+                read_only: None,
+                write_only: None,
+            }],
+            ..Config::default()
+        };
+        rebase_config(&mut config).unwrap();
+        assert_eq!(config.directory, PathBuf::from("/"));
+        assert_eq!(config.listeners[0].directory, Some(PathBuf::from("/boot")));
+
+        config.directory = PathBuf::from("/srv/tftp");
+        config.listeners[0].directory = Some(PathBuf::from("/etc"));
+        assert!(rebase_config(&mut config).is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use crate::sys;
+use crate::{Quotas, Stats};
+use std::cell::Cell;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// How many bytes an upload may receive between two checks of the free
+/// space, so that the file system is not queried for every block.
+const FREE_SPACE_CHECK_BYTES: u64 = 1024 * 1024;
+
+/// The [`Quotas`] a single upload is checked against while it is received.
+pub(crate) struct UploadQuota {
+    quotas: Quotas,
+    client: IpAddr,
+    directory: PathBuf,
+    stats: Stats,
+    /// The bytes received since the free space was last checked.
+    unchecked: Cell<u64>,
+}
+
+impl UploadQuota {
+    /// Creates the quota for an upload of `file` from `client`, whose usage is
+    /// counted in `stats`.
+    pub(crate) fn new(quotas: Quotas, client: IpAddr, file: &Path, stats: Stats) -> UploadQuota {
+        UploadQuota {
+            quotas,
+            client,
+            directory: file.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            stats,
+            unchecked: Cell::new(0),
+        }
+    }
+
+    /// Checks whether an upload announced to be `size` bytes long, or of an
+    /// unknown size if 0, may start. This is the only check of the free space
+    /// until [`FREE_SPACE_CHECK_BYTES`] have been received.
+    pub(crate) fn check_request(&self, size: u64) -> Result<(), &'static str> {
+        if self.quotas.max_file_size.is_some_and(|max| size > max) {
+            return Err("file too large");
+        }
+        if self
+            .quotas
+            .client_daily_bytes
+            .is_some_and(|limit| self.stats.uploaded_today(self.client) >= limit)
+        {
+            return Err("daily upload quota exceeded");
+        }
+
+        self.check_free_space(size)
+    }
+
+    /// Counts `bytes` more of an upload of which `received` bytes have
+    /// already arrived, or returns why the upload must stop.
+    pub(crate) fn add(&self, received: u64, bytes: u64) -> Result<(), &'static str> {
+        let result = self.try_add(received, bytes);
+        if result.is_err() {
+            self.stats.count_quota_exceeded();
+        }
+
+        result
+    }
+
+    fn try_add(&self, received: u64, bytes: u64) -> Result<(), &'static str> {
+        if self
+            .quotas
+            .max_file_size
+            .is_some_and(|max| received + bytes > max)
+        {
+            return Err("file too large");
+        }
+        let unchecked = self.unchecked.get() + bytes;
+        if unchecked >= FREE_SPACE_CHECK_BYTES {
+            self.check_free_space(bytes)?;
+            self.unchecked.set(0);
+        } else {
+            self.unchecked.set(unchecked);
+        }
+        if !self
+            .stats
+            .count_upload(self.client, bytes, self.quotas.client_daily_bytes)
+        {
+            return Err("daily upload quota exceeded");
+        }
+
+        Ok(())
+    }
+
+    /// Checks that writing `bytes` more leaves the free space watermark
+    /// untouched.
+    fn check_free_space(&self, bytes: u64) -> Result<(), &'static str> {
+        let Some(min_free_space) = self.quotas.min_free_space else {
+            return Ok(());
+        };
+
+        // The directory of the upload may not have been created yet.
+        let directory = self
+            .directory
+            .ancestors()
+            .find(|directory| directory.is_dir())
+            .unwrap_or(Path::new("."));
+        match sys::free_space(directory) {
+            Ok(free) if free.saturating_sub(bytes) >= min_free_space => Ok(()),
+            _ => Err("disk full"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_uploads_over_quota() {
+        let client = "10.0.0.1".parse().unwrap();
+        let stats = Stats::default();
+        let quota = UploadQuota::new(
+            Quotas {
+                max_file_size: Some(1000),
+                client_daily_bytes: Some(1500),
+                ..Default::default()
+            },
+            client,
+            Path::new("upload"),
+            stats.clone(),
+        );
+
+        assert_eq!(quota.check_request(0), Ok(()));
+        assert_eq!(quota.check_request(1001), Err("file too large"));
+        assert_eq!(quota.add(0, 600), Ok(()));
+        assert_eq!(quota.add(600, 401), Err("file too large"));
+        assert_eq!(quota.add(600, 400), Ok(()));
+        assert_eq!(quota.add(0, 600), Err("daily upload quota exceeded"));
+        assert_eq!(quota.add(0, 500), Ok(()));
+        assert_eq!(quota.check_request(0), Err("daily upload quota exceeded"));
+
+        assert_eq!(stats.uploaded_today(client), 1500);
+        assert_eq!(stats.uploaded(), 1500);
+        assert_eq!(stats.quota_exceeded(), 2);
+        assert_eq!(stats.uploaded_today("10.0.0.2".parse().unwrap()), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_free_space() {
+        let quota = |min_free_space| {
+            UploadQuota::new(
+                Quotas {
+                    min_free_space: Some(min_free_space),
+                    ..Default::default()
+                },
+                "10.0.0.1".parse().unwrap(),
+                Path::new("/upload"),
+                Stats::default(),
+            )
+        };
+
+        assert_eq!(quota(0).check_request(0), Ok(()));
+        assert_eq!(quota(0).add(0, FREE_SPACE_CHECK_BYTES), Ok(()));
+
+        let quota = quota(u64::MAX);
+        assert_eq!(quota.check_request(0), Err("disk full"));
+        // The free space is only checked again after a while.
+        assert_eq!(quota.add(0, 512), Ok(()));
+        assert_eq!(
+            quota.add(512, FREE_SPACE_CHECK_BYTES - 512),
+            Err("disk full")
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use crate::{RateLimit, RateLimits};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Number of tracked clients above which the least recently seen client is
+/// forgotten.
+const MAX_TRACKED_CLIENTS: usize = 4096;
+
+/// Interval at which clients with full buckets are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The outcome of checking a request against the [`RateLimits`].
+#[derive(Debug, PartialEq)]
+pub(crate) enum Verdict {
+    Allowed,
+    Limited,
+    Banned,
+    NewBan,
+}
+
+/// Token buckets of the clients and of the server as a whole. The limits
+/// are passed to every check, so that they can change on reload without
+/// losing the state of the buckets.
+pub(crate) struct RateLimiter {
+    clients: HashMap<IpAddr, ClientState>,
+    global: Option<Bucket>,
+    pruned: Option<Instant>,
+}
+
+struct ClientState {
+    bucket: Bucket,
+    last_seen: Instant,
+    strikes: u32,
+    banned_until: Option<Instant>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new() -> RateLimiter {
+        RateLimiter {
+            clients: HashMap::new(),
+            global: None,
+            pruned: None,
+        }
+    }
+
+    /// Checks a request from `client` arriving at `now`, and takes a token
+    /// from the buckets if it is allowed. Clients are banned once
+    /// [`RateLimits::ban_after`] of their requests in a row were over their
+    /// limit.
+    pub(crate) fn check(&mut self, limits: &RateLimits, client: IpAddr, now: Instant) -> Verdict {
+        if self
+            .pruned
+            .is_none_or(|pruned| now.saturating_duration_since(pruned) >= PRUNE_INTERVAL)
+        {
+            self.prune(limits, now);
+            self.pruned = Some(now);
+        }
+
+        if let Some(limit) = &limits.client {
+            if self.clients.len() >= MAX_TRACKED_CLIENTS && !self.clients.contains_key(&client) {
+                self.evict_least_recently_seen();
+            }
+            let state = self.clients.entry(client).or_insert_with(|| ClientState {
+                bucket: Bucket::new(limit, now),
+                last_seen: now,
+                strikes: 0,
+                banned_until: None,
+            });
+            state.last_seen = now;
+            if let Some(banned_until) = state.banned_until {
+                if now < banned_until {
+                    return Verdict::Banned;
+                }
+                state.banned_until = None;
+            }
+
+            state.bucket.refill(limit, now);
+            if state.bucket.tokens < 1.0 {
+                state.strikes += 1;
+                if limits
+                    .ban_after
+                    .is_some_and(|ban_after| state.strikes >= ban_after)
+                {
+                    state.strikes = 0;
+                    state.banned_until = Some(now + limits.ban_duration);
+                    return Verdict::NewBan;
+                }
+                return Verdict::Limited;
+            }
+        }
+
+        if let Some(limit) = &limits.global {
+            let bucket = self.global.get_or_insert_with(|| Bucket::new(limit, now));
+            bucket.refill(limit, now);
+            if bucket.tokens < 1.0 {
+                return Verdict::Limited;
+            }
+            bucket.tokens -= 1.0;
+        }
+
+        if limits.client.is_some() {
+            if let Some(state) = self.clients.get_mut(&client) {
+                state.bucket.tokens -= 1.0;
+                state.strikes = 0;
+            }
+        }
+
+        Verdict::Allowed
+    }
+
+    /// Forgets the clients that are not banned and whose buckets are full,
+    /// since they behave like clients that were never seen.
+    fn prune(&mut self, limits: &RateLimits, now: Instant) {
+        let Some(limit) = &limits.client else {
+            self.clients.clear();
+            return;
+        };
+
+        self.clients.retain(|_, state| {
+            state.bucket.refill(limit, now);
+            state.banned_until.is_some_and(|until| now < until)
+                || state.bucket.tokens < f64::from(limit.burst)
+        });
+    }
+
+    /// Forgets the client seen longest ago, to make room for a new one.
+    fn evict_least_recently_seen(&mut self) {
+        let oldest = self
+            .clients
+            .iter()
+            .min_by_key(|(_, state)| state.last_seen)
+            .map(|(client, _)| *client);
+        if let Some(client) = oldest {
+            self.clients.remove(&client);
+        }
+    }
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit, now: Instant) -> Bucket {
+        Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * f64::from(limit.rate)).min(f64::from(limit.burst));
+        self.updated = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn limits_requests_of_each_client() {
+        let limits = RateLimits {
+            client: Some(RateLimit { rate: 2, burst: 3 }),
+            ..Default::default()
+        };
+        let mut limiter = RateLimiter::new();
+        let client = "10.0.0.1".parse().unwrap();
+        let other = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+
+        let checks = [
+            (client, 0, Verdict::Allowed),
+            (client, 0, Verdict::Allowed),
+            (client, 0, Verdict::Allowed),
+            (client, 0, Verdict::Limited),
+            (other, 0, Verdict::Allowed),
+            (client, 250, Verdict::Limited),
+            (client, 500, Verdict::Allowed),
+            (client, 500, Verdict::Limited),
+            (client, 5000, Verdict::Allowed),
+            (client, 5000, Verdict::Allowed),
+            (client, 5000, Verdict::Allowed),
+            (client, 5000, Verdict::Limited),
+        ];
+        for (index, (address, millis, expected)) in checks.into_iter().enumerate() {
+            let now = start + Duration::from_millis(millis);
+            assert_eq!(
+                limiter.check(&limits, address, now),
+                expected,
+                "check {index}"
+            );
+        }
+    }
+
+    #[test]
+    fn limits_requests_of_all_clients() {
+        let limits = RateLimits {
+            global: Some(RateLimit { rate: 1, burst: 2 }),
+            ..Default::default()
+        };
+        let mut limiter = RateLimiter::new();
+        let now = Instant::now();
+
+        for (client, expected) in [
+            ("10.0.0.1", Verdict::Allowed),
+            ("10.0.0.2", Verdict::Allowed),
+            ("10.0.0.3", Verdict::Limited),
+        ] {
+            assert_eq!(
+                limiter.check(&limits, client.parse().unwrap(), now),
+                expected,
+                "{client}"
+            );
+        }
+        assert_eq!(
+            limiter.check(
+                &limits,
+                "10.0.0.3".parse().unwrap(),
+                now + Duration::from_secs(1)
+            ),
+            Verdict::Allowed
+        );
+    }
+
+    #[test]
+    fn bans_clients_that_stay_over_the_limit() {
+        let limits = RateLimits {
+            client: Some(RateLimit { rate: 1, burst: 1 }),
+            ban_after: Some(2),
+            ban_duration: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let mut limiter = RateLimiter::new();
+        let client = "fd00::1".parse().unwrap();
+        let start = Instant::now();
+
+        let checks = [
+            (0, Verdict::Allowed),
+            (0, Verdict::Limited),
+            (1000, Verdict::Allowed),
+            (1000, Verdict::Limited),
+            (1000, Verdict::NewBan),
+            (5000, Verdict::Banned),
+            (10999, Verdict::Banned),
+            (11000, Verdict::Allowed),
+        ];
+        for (millis, expected) in checks {
+            let now = start + Duration::from_millis(millis);
+            assert_eq!(limiter.check(&limits, client, now), expected, "{millis}ms");
+        }
+    }
+
+    #[test]
+    fn bounds_the_number_of_tracked_clients() {
+        let limits = RateLimits {
+            client: Some(RateLimit { rate: 1, burst: 3 }),
+            ..Default::default()
+        };
+        let mut limiter = RateLimiter::new();
+        let start = Instant::now();
+        let client = |index: usize| IpAddr::from([10, 0, (index >> 8) as u8, index as u8]);
+
+        for index in 0..MAX_TRACKED_CLIENTS + 100 {
+            let now = start + Duration::from_millis(index as u64);
+            for _ in 0..2 {
+                assert_eq!(limiter.check(&limits, client(index), now), Verdict::Allowed);
+            }
+            assert!(limiter.clients.len() <= MAX_TRACKED_CLIENTS);
+        }
+        assert!(!limiter.clients.contains_key(&client(99)));
+        assert!(limiter.clients.contains_key(&client(100)));
+
+        let later = start + PRUNE_INTERVAL + Duration::from_secs(10);
+        limiter.check(&limits, client(0), later);
+        assert_eq!(limiter.clients.len(), 1);
+    }
+
+    #[test]
+    fn allows_everything_without_limits() {
+        let mut limiter = RateLimiter::new();
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            assert_eq!(
+                limiter.check(&RateLimits::default(), "10.0.0.1".parse().unwrap(), now),
+                Verdict::Allowed
+            );
+        }
+        assert!(limiter.clients.is_empty());
+    }
+}
@@ -1,16 +1,44 @@
-use crate::{Config, OptionType, ServerSocket, Socket, Worker};
-use crate::{ErrorCode, Packet, TransferOption};
-use std::cmp::max;
+use crate::glob::Rules;
+use crate::overwrite;
+use crate::policy::{self, Policy, RequestPolicy};
+use crate::quota::UploadQuota;
+use crate::ratelimit::{RateLimiter, Verdict};
+use crate::sys;
+use crate::upload;
+use crate::{
+    AccessList, Config, Listener, Mount, OptionLimits, OptionType, OverwritePolicy, Profile,
+    Quotas, RateLimits, RetryPolicy, SymlinkPolicy,
+};
+use crate::{ErrorCode, Opcode, Packet, TransferOption};
+use crate::{ServerSocket, Socket, Stats, Worker};
+use std::cmp::min;
 use std::collections::HashMap;
 use std::error::Error;
-use std::net::{SocketAddr, UdpSocket};
-use std::path::{Path, PathBuf};
-use std::sync::mpsc::Sender;
-use std::time::Duration;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::ops::RangeInclusive;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 const DEFAULT_BLOCK_SIZE: usize = 512;
 const DEFAULT_WINDOW_SIZE: u16 = 1;
+const DEFAULT_INETD_IDLE_TIMEOUT: Duration = Duration::from_secs(900);
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Callback invoked with every read and write request before the server
+/// handles it. Returning an [`ErrorCode`] rejects the request, and the
+/// client receives an error packet with that code.
+pub type RequestHook = Box<dyn Fn(&Packet, &SocketAddr) -> Result<(), ErrorCode> + Send + Sync>;
+
+/// Callback invoked when the server receives a SIGHUP, returning the
+/// [`Config`] to reload. If it returns an error, or a config that cannot be
+/// applied without a restart, the running config is kept.
+pub type ConfigSource = Box<dyn Fn() -> Result<Config, Box<dyn Error>> + Send + Sync>;
 
 /// Server `struct` is used for handling incoming TFTP requests.
 ///
@@ -27,111 +55,474 @@ const DEFAULT_WINDOW_SIZE: u16 = 1;
 /// let config = Config::new(args).unwrap();
 /// let server = Server::new(&config).unwrap();
 /// ```
+///
+/// Servers can also be created without parsing any arguments by using a
+/// [`ServerBuilder`].
 pub struct Server {
-    socket: UdpSocket,
-    directory: PathBuf,
-    single_port: bool,
-    read_only: bool,
-    largest_block_size: usize,
+    listeners: Vec<UdpSocket>,
+    config: Config,
+    sockets: usize,
+    policy: Policy,
+    idle_timeout: Option<Duration>,
+    request_hook: Option<RequestHook>,
+    config_source: Option<ConfigSource>,
+    largest_block_size: Arc<AtomicUsize>,
     clients: HashMap<SocketAddr, Sender<Packet>>,
+    sessions: HashMap<SessionKey, Session>,
+    rate_limiter: RateLimiter,
+    stats: Stats,
+}
+
+/// Identifies a transfer by the client address, the request opcode and the
+/// requested file name.
+#[derive(PartialEq, Eq, Hash)]
+struct SessionKey {
+    client: SocketAddr,
+    opcode: Opcode,
+    filename: String,
+}
+
+impl SessionKey {
+    fn new(opcode: Opcode, filename: &str, client: &SocketAddr) -> SessionKey {
+        SessionKey {
+            client: *client,
+            opcode,
+            filename: filename.to_string(),
+        }
+    }
+}
+
+/// A running transfer, along with the packet it started with so that it can
+/// be sent again when the client retransmits its request.
+struct Session {
+    handle: JoinHandle<()>,
+    socket: Box<dyn Socket>,
+    response: Packet,
+}
+
+/// The socket a [`Worker`] transfers a file with, and a second handle to the
+/// same socket for resending the first response.
+struct TransferSockets {
+    worker: Box<dyn Socket>,
+    response: Box<dyn Socket>,
+}
+
+struct Received {
+    listener: usize,
+    packet: Packet,
+    from: SocketAddr,
+    local: Option<IpAddr>,
 }
 
 impl Server {
     /// Creates the TFTP Server with the supplied [`Config`].
     pub fn new(config: &Config) -> Result<Server, Box<dyn Error>> {
-        let socket = UdpSocket::bind(SocketAddr::from((config.ip_address, config.port)))?;
-
-        let server = Server {
-            socket,
-            directory: config.directory.clone(),
-            single_port: config.single_port,
-            read_only: config.read_only,
-            largest_block_size: DEFAULT_BLOCK_SIZE,
-            clients: HashMap::new(),
-        };
+        ServerBuilder::from_config(config.clone()).build()
+    }
+
+    /// Returns the local [`SocketAddr`] the server is listening on. This is
+    /// useful for finding out the port chosen by the OS when binding to port 0.
+    /// If the server has multiple listeners, the address of the first one is
+    /// returned.
+    pub fn local_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        Ok(self.listeners[0].local_addr()?)
+    }
+
+    /// Returns the local [`SocketAddr`]s of all the listeners of the server.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, Box<dyn Error>> {
+        self.listeners
+            .iter()
+            .map(|listener| Ok(listener.local_addr()?))
+            .collect()
+    }
+
+    /// Removes the temporary files of uploads that were interrupted by a
+    /// crash from every directory clients may upload to, down to a few
+    /// levels of subdirectories. This is meant to be called once before
+    /// listening, by the only server using these directories.
+    pub fn remove_interrupted_uploads(&self) {
+        for directory in self.policy.writable_directories() {
+            upload::remove_leftovers(directory);
+        }
+    }
 
-        Ok(server)
+    /// Returns the [`Stats`] of the server, which keep counting while the
+    /// server is listening.
+    pub fn stats(&self) -> Stats {
+        self.stats.clone()
     }
 
-    /// Starts listening for connections. Note that this function does not finish running until termination.
+    /// Starts listening for connections on every listener. Note that this function does not finish running until termination,
+    /// unless an idle timeout is set and the server stays idle for that long.
+    ///
+    /// If a [`ConfigSource`] is set, the config is reloaded whenever the
+    /// process receives a SIGHUP.
     pub fn listen(&mut self) {
+        let receiver = match self.spawn_receivers() {
+            Ok(receiver) => receiver,
+            Err(err) => {
+                eprintln!("Could not start listening: {err}");
+                return;
+            }
+        };
+
+        let mut last_activity = Instant::now();
         loop {
-            let received = if self.single_port {
-                self.socket.recv_from_with_size(self.largest_block_size)
-            } else {
-                Socket::recv_from(&self.socket)
-            };
+            if self.config_source.is_some() && sys::take_sighup() {
+                self.reload();
+            }
 
-            if let Ok((packet, from)) = received {
-                match packet {
-                    Packet::Rrq {
-                        filename,
-                        mut options,
-                        ..
-                    } => {
-                        println!("Sending {filename} to {from}");
-                        if let Err(err) = self.handle_rrq(filename.clone(), &mut options, &from) {
-                            eprintln!("Error while sending file: {err}")
-                        }
-                    }
-                    Packet::Wrq {
-                        filename,
-                        mut options,
-                        ..
-                    } => {
-                        if self.read_only {
-                            if Socket::send_to(
-                                &self.socket,
-                                &Packet::Error {
-                                    code: ErrorCode::AccessViolation,
-                                    msg: "server is read-only".to_string(),
-                                },
-                                &from,
-                            )
-                            .is_err()
-                            {
-                                eprintln!("Could not send error packet");
-                            };
-                            eprintln!("Received invalid request");
+            let received = match self.poll_timeout() {
+                Some(timeout) => match receiver.recv_timeout(timeout) {
+                    Ok(received) => received,
+                    Err(RecvTimeoutError::Timeout) => {
+                        let Some(idle_timeout) = self.idle_timeout else {
+                            continue;
+                        };
+                        if last_activity.elapsed() < idle_timeout {
                             continue;
                         }
-                        println!("Receiving {filename} from {from}");
-                        if let Err(err) = self.handle_wrq(filename.clone(), &mut options, &from) {
-                            eprintln!("Error while receiving file: {err}")
+                        self.prune_sessions();
+                        if self.sessions.is_empty() {
+                            println!(
+                                "Stopping after {}s without requests",
+                                idle_timeout.as_secs()
+                            );
+                            return;
                         }
+                        last_activity = Instant::now();
+                        continue;
                     }
-                    _ => {
-                        if self.route_packet(packet, &from).is_err() {
-                            if Socket::send_to(
-                                &self.socket,
-                                &Packet::Error {
-                                    code: ErrorCode::IllegalOperation,
-                                    msg: "invalid request".to_string(),
-                                },
-                                &from,
-                            )
-                            .is_err()
-                            {
-                                eprintln!("Could not send error packet");
-                            };
-                            eprintln!("Received invalid request");
-                        }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                },
+                None => match receiver.recv() {
+                    Ok(received) => received,
+                    Err(_) => return,
+                },
+            };
+
+            last_activity = Instant::now();
+            self.handle_packet(received);
+        }
+    }
+
+    /// Returns how long to wait for a request before checking for an idle
+    /// timeout or a pending reload.
+    fn poll_timeout(&self) -> Option<Duration> {
+        match (self.idle_timeout, self.config_source.is_some()) {
+            (Some(idle_timeout), true) => Some(min(idle_timeout, RELOAD_POLL_INTERVAL)),
+            (Some(idle_timeout), false) => Some(idle_timeout),
+            (None, true) => Some(RELOAD_POLL_INTERVAL),
+            (None, false) => None,
+        }
+    }
+
+    /// Reads the config again and swaps the policy new requests are handled
+    /// with. Running transfers keep their settings.
+    fn reload(&mut self) {
+        let Some(config_source) = &self.config_source else {
+            return;
+        };
+
+        match config_source().and_then(|config| self.reloaded_policy(config)) {
+            Ok((config, policy)) => {
+                self.config = config;
+                self.policy = policy;
+                println!("Reloaded configuration");
+            }
+            Err(err) => eprintln!("Keeping the running configuration, reload failed: {err}"),
+        }
+    }
+
+    fn reloaded_policy(&self, config: Config) -> Result<(Config, Policy), Box<dyn Error>> {
+        let running = &self.config;
+        let restart_settings = [
+            (
+                "listen addresses",
+                listen_addresses(running, self.sockets) != listen_addresses(&config, self.sockets),
+            ),
+            (
+                "dual-stack setting",
+                running.dual_stack != config.dual_stack,
+            ),
+            (
+                "single port setting",
+                running.single_port != config.single_port,
+            ),
+            (
+                "socket activation setting",
+                running.socket_activation != config.socket_activation,
+            ),
+            ("inetd setting", running.inetd != config.inetd),
+            ("user", running.user != config.user),
+            ("group", running.group != config.group),
+            (
+                "chroot directory",
+                running.chroot != config.chroot
+                    || (config.chroot && running.directory != config.directory),
+            ),
+        ];
+        if let Some((setting, _)) = restart_settings.iter().find(|(_, changed)| *changed) {
+            return Err(format!("changing the {setting} requires a restart").into());
+        }
+
+        let policy = if config.chroot {
+            let mut rebased = config.clone();
+            policy::rebase_config(&mut rebased)?;
+            Policy::new(&rebased, self.sockets)?
+        } else {
+            Policy::new(&config, self.sockets)?
+        };
+
+        Ok((config, policy))
+    }
+
+    fn spawn_receivers(&self) -> Result<Receiver<Received>, Box<dyn Error>> {
+        let (sender, receiver) = mpsc::channel();
+
+        for (index, listener) in self.listeners.iter().enumerate() {
+            let socket = listener.try_clone()?;
+            let sender = sender.clone();
+            let single_port = self.config.single_port;
+            let largest_block_size = self.largest_block_size.clone();
+            // Sockets bound to a wildcard address need the destination of every
+            // request, so that transfers are answered from the same address.
+            let packet_info = socket.local_addr()?.ip().is_unspecified()
+                && sys::enable_packet_info(&socket).is_ok();
+
+            thread::spawn(move || loop {
+                let size = if single_port {
+                    largest_block_size.load(Ordering::Relaxed)
+                } else {
+                    DEFAULT_BLOCK_SIZE
+                };
+
+                if let Ok(received) = receive_request(&socket, index, size, packet_info) {
+                    if sender.send(received).is_err() {
+                        break;
                     }
+                }
+            });
+        }
+
+        Ok(receiver)
+    }
+
+    fn handle_packet(&mut self, received: Received) {
+        let Received {
+            listener,
+            packet,
+            from,
+            local,
+        } = received;
+        let local = match local {
+            Some(local) => local,
+            None => match self.listeners[listener].local_addr() {
+                Ok(addr) => addr.ip(),
+                Err(err) => {
+                    eprintln!("Could not get listener address: {err}");
+                    return;
+                }
+            },
+        };
+
+        if let Err(code) = self.check_request_hook(&packet, &from) {
+            self.send_error(listener, code, "request rejected", &from);
+            eprintln!("Rejected request from {from}");
+            return;
+        }
+
+        match packet {
+            Packet::Rrq {
+                filename,
+                mut options,
+                ..
+            } => {
+                if !self.check_rate_limits(&from) {
+                    return;
+                }
+                if !self.check_access(listener, Opcode::Rrq, &from) {
+                    return;
+                }
+                let Some(policy) = self.policy.for_request(listener, &from.ip(), &filename) else {
+                    self.send_error(
+                        listener,
+                        ErrorCode::AccessViolation,
+                        "file access violation",
+                        &from,
+                    );
+                    eprintln!("Received invalid request");
+                    return;
+                };
+                if policy.write_only {
+                    self.send_error(
+                        listener,
+                        ErrorCode::AccessViolation,
+                        "file access violation",
+                        &from,
+                    );
+                    eprintln!("Received invalid request");
+                    return;
+                }
+                if self.retrigger_session(Opcode::Rrq, &filename, &from) {
+                    return;
+                }
+                println!("Sending {filename} to {from}");
+                if let Err(err) = self.handle_rrq(
+                    listener,
+                    local,
+                    filename.clone(),
+                    &policy,
+                    &mut options,
+                    &from,
+                ) {
+                    eprintln!("Error while sending file: {err}")
+                }
+            }
+            Packet::Wrq {
+                filename,
+                mut options,
+                ..
+            } => {
+                if !self.check_rate_limits(&from) {
+                    return;
+                }
+                if !self.check_access(listener, Opcode::Wrq, &from) {
+                    return;
+                }
+                let Some(policy) = self.policy.for_request(listener, &from.ip(), &filename) else {
+                    self.send_error(
+                        listener,
+                        ErrorCode::AccessViolation,
+                        "file access violation",
+                        &from,
+                    );
+                    eprintln!("Received invalid request");
+                    return;
                 };
+                if policy.read_only {
+                    self.send_error(
+                        listener,
+                        ErrorCode::AccessViolation,
+                        "server is read-only",
+                        &from,
+                    );
+                    eprintln!("Received invalid request");
+                    return;
+                }
+                if self.retrigger_session(Opcode::Wrq, &filename, &from) {
+                    return;
+                }
+                println!("Receiving {filename} from {from}");
+                if let Err(err) = self.handle_wrq(
+                    listener,
+                    local,
+                    filename.clone(),
+                    &policy,
+                    &mut options,
+                    &from,
+                ) {
+                    eprintln!("Error while receiving file: {err}")
+                }
+            }
+            _ => {
+                if self.route_packet(packet, &from).is_err() {
+                    self.send_error(
+                        listener,
+                        ErrorCode::IllegalOperation,
+                        "invalid request",
+                        &from,
+                    );
+                    eprintln!("Received invalid request");
+                }
+            }
+        };
+    }
+
+    fn send_error(&self, listener: usize, code: ErrorCode, msg: &str, to: &SocketAddr) {
+        if Socket::send_to(
+            &self.listeners[listener],
+            &Packet::Error {
+                code,
+                msg: msg.to_string(),
+            },
+            to,
+        )
+        .is_err()
+        {
+            eprintln!("Could not send error packet");
+        };
+    }
+
+    /// Checks a read or write request against the rate limits. Requests over
+    /// a limit are dropped silently, so that a flood of requests is not
+    /// answered with a flood of errors.
+    fn check_rate_limits(&mut self, from: &SocketAddr) -> bool {
+        self.stats.count_request();
+
+        match self.rate_limiter.check(
+            &self.policy.rate_limits,
+            from.ip().to_canonical(),
+            Instant::now(),
+        ) {
+            Verdict::Allowed => return true,
+            Verdict::Limited => self.stats.count_rate_limited(),
+            Verdict::Banned => self.stats.count_banned(),
+            Verdict::NewBan => {
+                self.stats.count_ban();
+                self.stats.count_rate_limited();
+                eprintln!(
+                    "Banned {} for {}s after too many requests",
+                    from.ip(),
+                    self.policy.rate_limits.ban_duration.as_secs()
+                );
             }
         }
+
+        false
+    }
+
+    /// Checks a read or write request against its access list, and logs the
+    /// rule that denies it. Denied requests are answered with an access
+    /// violation unless they are dropped silently.
+    fn check_access(&self, listener: usize, opcode: Opcode, from: &SocketAddr) -> bool {
+        let (access, kind) = match opcode {
+            Opcode::Wrq => (&self.policy.write_access, "write"),
+            _ => (&self.policy.read_access, "read"),
+        };
+        let Some(rule) = access.denied_by(&from.ip()) else {
+            return true;
+        };
+
+        eprintln!("Denied {kind} request from {from} by rule: {rule}");
+        if !self.policy.drop_denied {
+            self.send_error(listener, ErrorCode::AccessViolation, "access denied", from);
+        }
+
+        false
+    }
+
+    fn check_request_hook(&self, packet: &Packet, from: &SocketAddr) -> Result<(), ErrorCode> {
+        match (packet, &self.request_hook) {
+            (Packet::Rrq { .. } | Packet::Wrq { .. }, Some(hook)) => hook(packet, from),
+            _ => Ok(()),
+        }
     }
 
     fn handle_rrq(
         &mut self,
+        listener: usize,
+        local: IpAddr,
         filename: String,
+        policy: &RequestPolicy,
         options: &mut [TransferOption],
         to: &SocketAddr,
     ) -> Result<(), Box<dyn Error>> {
-        let file_path = &self.directory.join(filename);
-        match check_file_exists(file_path, &self.directory) {
+        let (status, file_path) = check_file_exists(policy, &self.policy.read_files);
+        let file_path = &file_path;
+        match status {
             ErrorCode::FileNotFound => Socket::send_to(
-                &self.socket,
+                &self.listeners[listener],
                 &Packet::Error {
                     code: ErrorCode::FileNotFound,
                     msg: "file does not exist".to_string(),
@@ -139,7 +530,7 @@ impl Server {
                 to,
             ),
             ErrorCode::AccessViolation => Socket::send_to(
-                &self.socket,
+                &self.listeners[listener],
                 &Packet::Error {
                     code: ErrorCode::AccessViolation,
                     msg: "file access violation".to_string(),
@@ -147,20 +538,16 @@ impl Server {
                 to,
             ),
             ErrorCode::FileExists => {
-                let worker_options =
-                    parse_options(options, RequestType::Read(file_path.metadata()?.len()))?;
-                let mut socket: Box<dyn Socket>;
-
-                if self.single_port {
-                    let single_socket = create_single_socket(&self.socket, to)?;
-                    self.clients.insert(*to, single_socket.sender());
-                    self.largest_block_size =
-                        max(self.largest_block_size, worker_options.block_size);
-
-                    socket = Box::new(single_socket);
-                } else {
-                    socket = Box::new(create_multi_socket(&self.socket.local_addr()?, to)?);
-                }
+                let worker_options = parse_options(
+                    options,
+                    RequestType::Read(file_path.symlink_metadata()?.len()),
+                    &policy.limits,
+                    &policy.retry,
+                )?;
+                let TransferSockets {
+                    worker: mut socket,
+                    response: response_socket,
+                } = self.create_sockets(listener, local, to, worker_options.block_size)?;
 
                 socket.set_read_timeout(worker_options.timeout)?;
                 socket.set_write_timeout(worker_options.timeout)?;
@@ -168,17 +555,37 @@ impl Server {
                 accept_request(
                     &socket,
                     options,
-                    RequestType::Read(file_path.metadata()?.len()),
+                    RequestType::Read(file_path.symlink_metadata()?.len()),
                 )?;
 
+                let response = if options.is_empty() {
+                    Packet::Data {
+                        block_num: 1,
+                        data: read_first_block(file_path, worker_options.block_size)?,
+                    }
+                } else {
+                    Packet::Oack(options.to_vec())
+                };
                 let worker = Worker::new(
                     socket,
                     file_path.clone(),
                     worker_options.block_size,
                     worker_options.timeout,
                     worker_options.window_size,
+                )
+                .max_retries(policy.retry.max_retries)
+                .follow_symlinks(false);
+                let handle = worker.send()?;
+                self.sessions.insert(
+                    SessionKey::new(Opcode::Rrq, &filename, to),
+                    Session {
+                        handle,
+                        socket: response_socket,
+                        response,
+                    },
                 );
-                worker.send()
+
+                Ok(())
             }
             _ => Err("Unexpected error code when checking file".into()),
         }
@@ -186,14 +593,19 @@ impl Server {
 
     fn handle_wrq(
         &mut self,
+        listener: usize,
+        local: IpAddr,
         file_name: String,
+        policy: &RequestPolicy,
         options: &mut [TransferOption],
         to: &SocketAddr,
     ) -> Result<(), Box<dyn Error>> {
-        let file_path = &self.directory.join(file_name);
-        match check_file_exists(file_path, &self.directory) {
-            ErrorCode::FileExists => Socket::send_to(
-                &self.socket,
+        let overwrite = self.policy.overwrite;
+        let (status, file_path) = check_file_exists(policy, &self.policy.write_files);
+        let file_path = &file_path;
+        match status {
+            ErrorCode::FileExists if !overwrite::allows(file_path, overwrite) => Socket::send_to(
+                &self.listeners[listener],
                 &Packet::Error {
                     code: ErrorCode::FileExists,
                     msg: "requested file already exists".to_string(),
@@ -201,46 +613,160 @@ impl Server {
                 to,
             ),
             ErrorCode::AccessViolation => Socket::send_to(
-                &self.socket,
+                &self.listeners[listener],
                 &Packet::Error {
                     code: ErrorCode::AccessViolation,
                     msg: "file access violation".to_string(),
                 },
                 to,
             ),
-            ErrorCode::FileNotFound => {
-                let worker_options = parse_options(options, RequestType::Write)?;
-                let mut socket: Box<dyn Socket>;
-
-                if self.single_port {
-                    let single_socket = create_single_socket(&self.socket, to)?;
-                    self.clients.insert(*to, single_socket.sender());
-                    self.largest_block_size =
-                        max(self.largest_block_size, worker_options.block_size);
-
-                    socket = Box::new(single_socket);
-                } else {
-                    socket = Box::new(create_multi_socket(&self.socket.local_addr()?, to)?);
+            ErrorCode::FileNotFound | ErrorCode::FileExists => {
+                let worker_options =
+                    parse_options(options, RequestType::Write, &policy.limits, &policy.retry)?;
+                let quota =
+                    UploadQuota::new(self.policy.quotas, to.ip(), file_path, self.stats.clone());
+                if let Err(msg) = quota.check_request(worker_options.transfer_size) {
+                    eprintln!("Refused upload of {} from {to}: {msg}", policy.name);
+                    self.stats.count_quota_exceeded();
+                    return Socket::send_to(
+                        &self.listeners[listener],
+                        &Packet::Error {
+                            code: ErrorCode::DiskFull,
+                            msg: msg.to_string(),
+                        },
+                        to,
+                    );
                 }
+                let create = self.policy.create_dirs;
+                let file_path = match create_parent_dirs(policy, file_path, create) {
+                    Ok(file_path) => file_path,
+                    Err(err) => {
+                        eprintln!("Refused upload of {} from {to}: {err}", policy.name);
+                        let (code, msg) = match err.kind() {
+                            io::ErrorKind::NotFound => {
+                                (ErrorCode::FileNotFound, "directory does not exist")
+                            }
+                            _ => (ErrorCode::AccessViolation, "could not create directory"),
+                        };
+                        return Socket::send_to(
+                            &self.listeners[listener],
+                            &Packet::Error {
+                                code,
+                                msg: msg.to_string(),
+                            },
+                            to,
+                        );
+                    }
+                };
+                // The path may have changed while the directories were created.
+                let quota =
+                    UploadQuota::new(self.policy.quotas, to.ip(), &file_path, self.stats.clone());
+                let TransferSockets {
+                    worker: mut socket,
+                    response: response_socket,
+                } = self.create_sockets(listener, local, to, worker_options.block_size)?;
 
                 socket.set_read_timeout(worker_options.timeout)?;
                 socket.set_write_timeout(worker_options.timeout)?;
 
                 accept_request(&socket, options, RequestType::Write)?;
 
+                let response = if options.is_empty() {
+                    Packet::Ack(0)
+                } else {
+                    Packet::Oack(options.to_vec())
+                };
                 let worker = Worker::new(
                     socket,
-                    file_path.clone(),
+                    file_path,
                     worker_options.block_size,
                     worker_options.timeout,
                     worker_options.window_size,
+                )
+                .max_retries(policy.retry.max_retries)
+                .overwrite(overwrite)
+                .quota(quota)
+                .attributes(policy.attributes);
+                let handle = worker.receive()?;
+                self.sessions.insert(
+                    SessionKey::new(Opcode::Wrq, &file_name, to),
+                    Session {
+                        handle,
+                        socket: response_socket,
+                        response,
+                    },
                 );
-                worker.receive()
+
+                Ok(())
             }
             _ => Err("Unexpected error code when checking file".into()),
         }
     }
 
+    fn create_sockets(
+        &mut self,
+        listener: usize,
+        local: IpAddr,
+        to: &SocketAddr,
+        block_size: usize,
+    ) -> Result<TransferSockets, Box<dyn Error>> {
+        let socket = &self.listeners[listener];
+
+        if self.config.single_port {
+            let single_socket = create_single_socket(socket, to)?;
+            self.clients.insert(*to, single_socket.sender());
+            self.largest_block_size
+                .fetch_max(block_size, Ordering::Relaxed);
+
+            let response_socket = create_single_socket(socket, to)?;
+
+            Ok(TransferSockets {
+                worker: Box::new(single_socket),
+                response: Box::new(response_socket),
+            })
+        } else {
+            let Some(multi_socket) = create_multi_socket(
+                local,
+                to,
+                self.config.dual_stack,
+                self.policy.port_range.as_ref(),
+            )?
+            else {
+                self.send_error(listener, ErrorCode::NotDefined, "no free transfer port", to);
+                return Err("All ports in the transfer port range are in use".into());
+            };
+            let response_socket = multi_socket.try_clone()?;
+
+            Ok(TransferSockets {
+                worker: Box::new(multi_socket),
+                response: Box::new(response_socket),
+            })
+        }
+    }
+
+    /// Answers a retransmitted request that belongs to a running session by
+    /// sending the first response of that session again. Returns `true` if
+    /// the request was a duplicate.
+    fn retrigger_session(&mut self, opcode: Opcode, filename: &str, from: &SocketAddr) -> bool {
+        self.prune_sessions();
+
+        let Some(session) = self.sessions.get(&SessionKey::new(opcode, filename, from)) else {
+            return false;
+        };
+
+        println!("Received duplicate request for {filename} from {from}");
+        if session.socket.send(&session.response).is_err() {
+            eprintln!("Could not resend response to {from}");
+        }
+
+        true
+    }
+
+    fn prune_sessions(&mut self) {
+        self.sessions
+            .retain(|_, session| !session.handle.is_finished());
+    }
+
     fn route_packet(&self, packet: Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
         if self.clients.contains_key(to) {
             self.clients[to].send(packet)?;
@@ -251,154 +777,1122 @@ impl Server {
     }
 }
 
-#[derive(Debug, PartialEq)]
-struct WorkerOptions {
-    block_size: usize,
-    transfer_size: u64,
-    timeout: Duration,
-    window_size: u16,
+/// ServerBuilder `struct` is used for creating a [`Server`] programmatically,
+/// without parsing any command line arguments.
+///
+/// Every setting of [`Config`] has a typed setter, and settings that are not
+/// supplied keep their default values. Binding to port `0` lets the OS choose
+/// a free port, which can be queried with [`Server::local_addr()`].
+///
+/// # Example
+///
+/// ```rust
+/// use std::net::Ipv4Addr;
+/// use tftpd::{ErrorCode, Packet, ServerBuilder};
+///
+/// let server = ServerBuilder::new()
+///     .ip_address(Ipv4Addr::LOCALHOST)
+///     .port(0)
+///     .directory(".")
+///     .read_only(true)
+///     .max_block_size(1468)
+///     .request_hook(|packet, _from| match packet {
+///         Packet::Rrq { filename, .. } if filename.ends_with(".key") => {
+///             Err(ErrorCode::AccessViolation)
+///         }
+///         _ => Ok(()),
+///     })
+///     .build()
+///     .unwrap();
+///
+/// assert_ne!(server.local_addr().unwrap().port(), 0);
+/// ```
+pub struct ServerBuilder {
+    config: Config,
+    sockets: Vec<UdpSocket>,
+    request_hook: Option<RequestHook>,
+    config_source: Option<ConfigSource>,
 }
 
-#[derive(Debug, PartialEq)]
-enum RequestType {
-    Read(u64),
-    Write,
-}
+impl ServerBuilder {
+    /// Creates a new [`ServerBuilder`] with the default settings.
+    pub fn new() -> ServerBuilder {
+        ServerBuilder::from_config(Config::default())
+    }
 
-fn parse_options(
-    options: &mut [TransferOption],
-    request_type: RequestType,
-) -> Result<WorkerOptions, &'static str> {
-    let mut worker_options = WorkerOptions {
-        block_size: DEFAULT_BLOCK_SIZE,
-        transfer_size: 0,
-        timeout: DEFAULT_TIMEOUT,
-        window_size: DEFAULT_WINDOW_SIZE,
-    };
+    /// Creates a new [`ServerBuilder`] starting from the supplied [`Config`].
+    pub fn from_config(config: Config) -> ServerBuilder {
+        ServerBuilder {
+            config,
+            sockets: Vec::new(),
+            request_hook: None,
+            config_source: None,
+        }
+    }
 
-    for option in options {
-        let TransferOption {
-            option: option_type,
-            value,
-        } = option;
+    /// Sets the local IP address of the server. Both IPv4 and IPv6 addresses
+    /// are supported.
+    pub fn ip_address<I: Into<IpAddr>>(mut self, ip_address: I) -> ServerBuilder {
+        self.config.ip_address = ip_address.into();
+        self
+    }
 
-        match option_type {
-            OptionType::BlockSize => worker_options.block_size = *value,
-            OptionType::TransferSize => match request_type {
-                RequestType::Read(size) => {
-                    *value = size as usize;
-                    worker_options.transfer_size = size;
-                }
-                RequestType::Write => worker_options.transfer_size = *value as u64,
-            },
-            OptionType::Timeout => {
-                if *value == 0 {
-                    return Err("Invalid timeout value");
-                }
-                worker_options.timeout = Duration::from_secs(*value as u64);
-            }
-            OptionType::Windowsize => {
-                if *value == 0 || *value > u16::MAX as usize {
-                    return Err("Invalid windowsize value");
-                }
-                worker_options.window_size = *value as u16;
-            }
-        }
+    /// Sets whether the server listens on `::` for both IPv6 and IPv4 clients,
+    /// ignoring the IP address.
+    pub fn dual_stack(mut self, dual_stack: bool) -> ServerBuilder {
+        self.config.dual_stack = dual_stack;
+        self
     }
 
-    Ok(worker_options)
-}
+    /// Sets the local port of the server. Use `0` to let the OS choose one.
+    pub fn port(mut self, port: u16) -> ServerBuilder {
+        self.config.port = port;
+        self
+    }
 
-fn create_single_socket(
-    socket: &UdpSocket,
-    remote: &SocketAddr,
-) -> Result<ServerSocket, Box<dyn Error>> {
-    let socket = ServerSocket::new(socket.try_clone()?, *remote);
+    /// Sets the directory files are served from and received into.
+    pub fn directory<P: Into<PathBuf>>(mut self, directory: P) -> ServerBuilder {
+        self.config.directory = directory.into();
+        self
+    }
 
-    Ok(socket)
-}
+    /// Sets whether a single port is used for both sending and receiving.
+    pub fn single_port(mut self, single_port: bool) -> ServerBuilder {
+        self.config.single_port = single_port;
+        self
+    }
 
-fn create_multi_socket(
-    addr: &SocketAddr,
-    remote: &SocketAddr,
-) -> Result<UdpSocket, Box<dyn Error>> {
-    let socket = UdpSocket::bind(SocketAddr::from((addr.ip(), 0)))?;
-    socket.connect(remote)?;
+    /// Sets whether all write requests are refused.
+    pub fn read_only(mut self, read_only: bool) -> ServerBuilder {
+        self.config.read_only = read_only;
+        self
+    }
 
-    Ok(socket)
-}
+    /// Adds a [`Mount`]. Requests are served from the mount with the longest
+    /// prefix matching the file name.
+    pub fn mount(mut self, mount: Mount) -> ServerBuilder {
+        self.config.mounts.push(mount);
+        self
+    }
 
-fn accept_request<T: Socket>(
-    socket: &T,
-    options: &[TransferOption],
-    request_type: RequestType,
-) -> Result<(), Box<dyn Error>> {
-    if !options.is_empty() {
-        socket.send(&Packet::Oack(options.to_vec()))?;
-        if let RequestType::Read(_) = request_type {
-            check_response(socket)?;
-        }
-    } else if request_type == RequestType::Write {
-        socket.send(&Packet::Ack(0))?;
+    /// Adds a [`Profile`]. Profiles are matched against the client address in
+    /// the order they are added.
+    pub fn profile(mut self, profile: Profile) -> ServerBuilder {
+        self.config.profiles.push(profile);
+        self
     }
 
-    Ok(())
-}
+    /// Sets the timeout and number of retries of transfers.
+    pub fn retry(mut self, retry: RetryPolicy) -> ServerBuilder {
+        self.config.retry = retry;
+        self
+    }
 
-fn check_response<T: Socket>(socket: &T) -> Result<(), Box<dyn Error>> {
-    if let Packet::Ack(received_block_number) = socket.recv()? {
-        if received_block_number != 0 {
-            socket.send(&Packet::Error {
-                code: ErrorCode::IllegalOperation,
-                msg: "invalid oack response".to_string(),
-            })?;
-        }
+    /// Sets whether all read requests are refused.
+    pub fn write_only(mut self, write_only: bool) -> ServerBuilder {
+        self.config.write_only = write_only;
+        self
+    }
+
+    /// Sets the clients allowed to read files.
+    pub fn read_access(mut self, read_access: AccessList) -> ServerBuilder {
+        self.config.read_access = read_access;
+        self
+    }
+
+    /// Sets the clients allowed to write files.
+    pub fn write_access(mut self, write_access: AccessList) -> ServerBuilder {
+        self.config.write_access = write_access;
+        self
+    }
+
+    /// Sets the limits for the rate of requests from clients.
+    pub fn rate_limits(mut self, rate_limits: RateLimits) -> ServerBuilder {
+        self.config.rate_limits = rate_limits;
+        self
+    }
+
+    /// Sets whether denied requests are dropped silently instead of being
+    /// answered with an access violation.
+    pub fn drop_denied(mut self, drop_denied: bool) -> ServerBuilder {
+        self.config.drop_denied = drop_denied;
+        self
+    }
+
+    /// Sets which symbolic links are followed when resolving requested
+    /// files.
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> ServerBuilder {
+        self.config.symlinks = symlinks;
+        self
+    }
+
+    /// Sets the limits for the size of uploads and the space they leave
+    /// free.
+    pub fn quotas(mut self, quotas: Quotas) -> ServerBuilder {
+        self.config.quotas = quotas;
+        self
+    }
+
+    /// Sets whether the missing parent directories of uploaded files are
+    /// created.
+    pub fn create_dirs(mut self, create_dirs: bool) -> ServerBuilder {
+        self.config.create_dirs = create_dirs;
+        self
+    }
+
+    /// Sets the permission bits of the directories created for uploads.
+    pub fn dir_mode(mut self, dir_mode: u32) -> ServerBuilder {
+        self.config.dir_mode = dir_mode;
+        self
+    }
+
+    /// Sets the permission bits of uploaded files instead of leaving them to
+    /// the umask.
+    pub fn file_mode(mut self, file_mode: u32) -> ServerBuilder {
+        self.config.file_mode = Some(file_mode);
+        self
+    }
+
+    /// Sets the user, by name or id, that owns uploaded files and created
+    /// directories.
+    pub fn file_owner(mut self, owner: &str) -> ServerBuilder {
+        self.config.file_owner = Some(owner.to_string());
+        self
+    }
+
+    /// Sets the group, by name or id, that owns uploaded files and created
+    /// directories.
+    pub fn file_group(mut self, group: &str) -> ServerBuilder {
+        self.config.file_group = Some(group.to_string());
+        self
+    }
+
+    /// Sets whether uploads may replace existing files.
+    pub fn overwrite(mut self, overwrite: OverwritePolicy) -> ServerBuilder {
+        self.config.overwrite = overwrite;
+        self
+    }
+
+    /// Adds a [`Listener`]. Once a listener is added, the server no longer
+    /// listens on the IP address and port settings.
+    pub fn listener(mut self, listener: Listener) -> ServerBuilder {
+        self.config.listeners.push(listener);
+        self
+    }
+
+    /// Adds an already bound [`UdpSocket`] to listen on, using the directory and
+    /// read-only settings. Once a socket is added, the server no longer listens
+    /// on the IP address and port settings.
+    pub fn socket(mut self, socket: UdpSocket) -> ServerBuilder {
+        self.sockets.push(socket);
+        self
+    }
+
+    /// Sets whether the sockets passed by systemd socket activation are used
+    /// instead of the IP address and port settings.
+    pub fn socket_activation(mut self, socket_activation: bool) -> ServerBuilder {
+        self.config.socket_activation = socket_activation;
+        self
+    }
+
+    /// Sets whether the socket passed by inetd as the standard input in `wait`
+    /// mode is used instead of the IP address and port settings. Unless an idle
+    /// timeout is set, the server stops after 900 idle seconds in this mode.
+    pub fn inetd(mut self, inetd: bool) -> ServerBuilder {
+        self.config.inetd = inetd;
+        self
+    }
+
+    /// Sets how long the server may stay idle, without requests or running
+    /// transfers, before [`Server::listen()`] returns.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> ServerBuilder {
+        self.config.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Sets the user the process switches to after binding the sockets.
+    pub fn user<S: Into<String>>(mut self, user: S) -> ServerBuilder {
+        self.config.user = Some(user.into());
+        self
+    }
+
+    /// Sets the group the process switches to after binding the sockets.
+    pub fn group<S: Into<String>>(mut self, group: S) -> ServerBuilder {
+        self.config.group = Some(group.into());
+        self
+    }
+
+    /// Sets whether the process is confined to the directory after binding
+    /// the sockets. Listener directories must be inside of it.
+    pub fn chroot(mut self, chroot: bool) -> ServerBuilder {
+        self.config.chroot = chroot;
+        self
+    }
+
+    /// Sets the range of local ports transfer sockets are bound to.
+    pub fn port_range(mut self, port_range: RangeInclusive<u16>) -> ServerBuilder {
+        self.config.port_range = Some(port_range);
+        self
+    }
+
+    /// Sets the upper bounds for the options negotiated with clients.
+    pub fn limits(mut self, limits: OptionLimits) -> ServerBuilder {
+        self.config.limits = limits;
+        self
+    }
+
+    /// Sets the largest block size the server agrees to.
+    pub fn max_block_size(mut self, max_block_size: usize) -> ServerBuilder {
+        self.config.limits.max_block_size = max_block_size;
+        self
+    }
+
+    /// Sets the largest window size the server agrees to.
+    pub fn max_window_size(mut self, max_window_size: u16) -> ServerBuilder {
+        self.config.limits.max_window_size = max_window_size;
+        self
+    }
+
+    /// Sets a [`RequestHook`] that is called with every read and write request
+    /// before it is handled.
+    pub fn request_hook<F>(mut self, hook: F) -> ServerBuilder
+    where
+        F: Fn(&Packet, &SocketAddr) -> Result<(), ErrorCode> + Send + Sync + 'static,
+    {
+        self.request_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets a [`ConfigSource`] that is called to reload the config when the
+    /// process receives a SIGHUP. Directories, read-only and write-only flags,
+    /// profiles, mounts, port range, option limits and retry policy take
+    /// effect for new requests, while other settings can only be changed by
+    /// restarting.
+    pub fn config_source<F>(mut self, config_source: F) -> ServerBuilder
+    where
+        F: Fn() -> Result<Config, Box<dyn Error>> + Send + Sync + 'static,
+    {
+        self.config_source = Some(Box::new(config_source));
+        self
+    }
+
+    /// Validates the settings, binds the socket and creates the [`Server`].
+    pub fn build(self) -> Result<Server, Box<dyn Error>> {
+        let config = self.config;
+
+        let mut sockets = self.sockets;
+        if config.socket_activation {
+            sockets.extend(sys::listen_fds()?);
+        }
+        if config.inetd {
+            sockets.push(sys::stdin_socket()?);
+        }
+        let mut policy = Policy::new(&config, sockets.len())?;
+        let idle_timeout = match config.idle_timeout {
+            None if config.inetd => Some(DEFAULT_INETD_IDLE_TIMEOUT),
+            idle_timeout => idle_timeout,
+        };
+
+        let inherited = sockets.len();
+        let mut listeners = sockets;
+        for address in listen_addresses(&config, inherited) {
+            listeners.push(sys::bind(address, config.dual_stack)?);
+        }
+
+        // Users and groups are looked up before the chroot, which most likely
+        // does not contain the user database.
+        let user = config.user.as_deref().map(sys::lookup_user).transpose()?;
+        let gid = match &config.group {
+            Some(group) => Some(sys::lookup_group(group)?),
+            None => user.map(|(_, gid)| gid),
+        };
+
+        if config.chroot {
+            let root = config.directory.canonicalize()?;
+            policy.chroot(&root)?;
+            sys::chroot(&config.directory)?;
+        }
+        if user.is_some() || gid.is_some() {
+            sys::drop_privileges(user.map(|(uid, _)| uid), gid)?;
+        }
+        if self.config_source.is_some() {
+            sys::catch_sighup()?;
+        }
+
+        Ok(Server {
+            listeners,
+            config,
+            sockets: inherited,
+            policy,
+            idle_timeout,
+            request_hook: self.request_hook,
+            config_source: self.config_source,
+            largest_block_size: Arc::new(AtomicUsize::new(DEFAULT_BLOCK_SIZE)),
+            clients: HashMap::new(),
+            sessions: HashMap::new(),
+            rate_limiter: RateLimiter::new(),
+            stats: Stats::default(),
+        })
+    }
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerBuilder::new()
+    }
+}
+
+/// Returns the addresses to bind listeners to. Without any listeners or
+/// already bound sockets, the server listens on the IP address and port
+/// settings.
+fn listen_addresses(config: &Config, sockets: usize) -> Vec<SocketAddr> {
+    if config.listeners.is_empty() && sockets == 0 {
+        let ip_address = if config.dual_stack {
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        } else {
+            config.ip_address
+        };
+        return vec![SocketAddr::from((ip_address, config.port))];
+    }
+
+    config
+        .listeners
+        .iter()
+        .map(|listener| listener.address)
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+struct WorkerOptions {
+    block_size: usize,
+    transfer_size: u64,
+    timeout: Duration,
+    window_size: u16,
+}
+
+#[derive(Debug, PartialEq)]
+enum RequestType {
+    Read(u64),
+    Write,
+}
+
+fn parse_options(
+    options: &mut [TransferOption],
+    request_type: RequestType,
+    limits: &OptionLimits,
+    retry: &RetryPolicy,
+) -> Result<WorkerOptions, &'static str> {
+    let mut worker_options = WorkerOptions {
+        block_size: DEFAULT_BLOCK_SIZE,
+        transfer_size: 0,
+        timeout: retry.timeout,
+        window_size: DEFAULT_WINDOW_SIZE,
+    };
+
+    for option in options {
+        let TransferOption {
+            option: option_type,
+            value,
+        } = option;
+
+        match option_type {
+            OptionType::BlockSize => {
+                *value = min(*value, limits.max_block_size);
+                worker_options.block_size = *value;
+            }
+            OptionType::TransferSize => match request_type {
+                RequestType::Read(size) => {
+                    *value = size as usize;
+                    worker_options.transfer_size = size;
+                }
+                RequestType::Write => worker_options.transfer_size = *value as u64,
+            },
+            OptionType::Timeout => {
+                if *value == 0 {
+                    return Err("Invalid timeout value");
+                }
+                worker_options.timeout = Duration::from_secs(*value as u64);
+            }
+            OptionType::Windowsize => {
+                if *value == 0 || *value > u16::MAX as usize {
+                    return Err("Invalid windowsize value");
+                }
+                *value = min(*value, limits.max_window_size as usize);
+                worker_options.window_size = *value as u16;
+            }
+        }
+    }
+
+    Ok(worker_options)
+}
+
+fn create_single_socket(
+    socket: &UdpSocket,
+    remote: &SocketAddr,
+) -> Result<ServerSocket, Box<dyn Error>> {
+    let socket = ServerSocket::new(socket.try_clone()?, *remote);
+
+    Ok(socket)
+}
+
+fn create_multi_socket(
+    local: IpAddr,
+    remote: &SocketAddr,
+    dual_stack: bool,
+    port_range: Option<&RangeInclusive<u16>>,
+) -> Result<Option<UdpSocket>, Box<dyn Error>> {
+    // Requests from IPv4 clients to a dual-stack listener arrive as IPv4-mapped
+    // addresses, which are answered from a plain IPv4 socket instead.
+    let (local, remote) = match (local.to_canonical(), remote.ip().to_canonical()) {
+        (IpAddr::V4(local), IpAddr::V4(remote_ip)) if !local.is_unspecified() => (
+            IpAddr::V4(local),
+            SocketAddr::from((remote_ip, remote.port())),
+        ),
+        _ => (local, *remote),
+    };
+
+    let socket = match port_range {
+        Some(port_range) => {
+            let mut ports = port_range.clone();
+            loop {
+                let Some(port) = ports.next() else {
+                    return Ok(None);
+                };
+                match sys::bind(SocketAddr::from((local, port)), dual_stack) {
+                    Ok(socket) => break socket,
+                    Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        None => sys::bind(SocketAddr::from((local, 0)), dual_stack)?,
+    };
+    socket.connect(remote)?;
+
+    Ok(Some(socket))
+}
+
+fn receive_request(
+    socket: &UdpSocket,
+    listener: usize,
+    size: usize,
+    packet_info: bool,
+) -> Result<Received, Box<dyn Error>> {
+    let (packet, from, local) = if packet_info {
+        let mut buf = vec![0; size + 4];
+        let (amt, from, local) = sys::recv_from_with_destination(socket, &mut buf)?;
+        (Packet::deserialize(&buf[..amt])?, from, local)
+    } else {
+        let (packet, from) = socket.recv_from_with_size(size)?;
+        (packet, from, None)
+    };
+
+    Ok(Received {
+        listener,
+        packet,
+        from,
+        local,
+    })
+}
+
+fn accept_request<T: Socket>(
+    socket: &T,
+    options: &[TransferOption],
+    request_type: RequestType,
+) -> Result<(), Box<dyn Error>> {
+    if !options.is_empty() {
+        socket.send(&Packet::Oack(options.to_vec()))?;
+        if let RequestType::Read(_) = request_type {
+            check_response(socket)?;
+        }
+    } else if request_type == RequestType::Write {
+        socket.send(&Packet::Ack(0))?;
+    }
+
+    Ok(())
+}
+
+fn check_response<T: Socket>(socket: &T) -> Result<(), Box<dyn Error>> {
+    if let Packet::Ack(received_block_number) = socket.recv()? {
+        if received_block_number != 0 {
+            socket.send(&Packet::Error {
+                code: ErrorCode::IllegalOperation,
+                msg: "invalid oack response".to_string(),
+            })?;
+        }
     }
 
     Ok(())
 }
 
-fn check_file_exists(file: &Path, directory: &PathBuf) -> ErrorCode {
-    if !validate_file_path(file, directory) {
-        return ErrorCode::AccessViolation;
+fn read_first_block(file: &Path, block_size: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut block = Vec::with_capacity(block_size);
+    sys::open_no_follow(file)?
+        .take(block_size as u64)
+        .read_to_end(&mut block)?;
+
+    Ok(block)
+}
+
+/// Checks whether the file of a request can be accessed and exists. Files
+/// denied by the `rules` are reported as an access violation whether they
+/// exist or not, so that clients cannot probe for them.
+///
+/// The file is returned with its symbolic links resolved, and transfers must
+/// use that path, so that a link swapped in after the check is not followed.
+fn check_file_exists(policy: &RequestPolicy, rules: &Rules) -> (ErrorCode, PathBuf) {
+    if let Some(rule) = rules.denied_by(&policy.name, policy.case_insensitive) {
+        eprintln!("Denied access to {} by rule: {rule}", policy.name);
+        return (ErrorCode::AccessViolation, policy.file.clone());
+    }
+
+    let Some(file) = resolve_file_path(&policy.file, &policy.directory, policy.symlinks) else {
+        return (ErrorCode::AccessViolation, policy.file.clone());
+    };
+
+    if file.symlink_metadata().is_err() {
+        return (ErrorCode::FileNotFound, file);
+    }
+
+    (ErrorCode::FileExists, file)
+}
+
+/// Makes sure the directory of the requested file, resolved to `file`,
+/// exists. If `create` is set, the missing directories are created with the
+/// attributes of the request, and the path of the file is resolved again.
+/// Returns the path to transfer the file to.
+fn create_parent_dirs(policy: &RequestPolicy, file: &Path, create: bool) -> io::Result<PathBuf> {
+    let Some(parent) = file.parent() else {
+        return Ok(file.to_path_buf());
+    };
+    if parent.is_dir() {
+        return Ok(file.to_path_buf());
+    }
+    if !create {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "directory does not exist",
+        ));
+    }
+
+    let denied = || io::Error::new(io::ErrorKind::PermissionDenied, "path is not allowed");
+    let missing = parent
+        .ancestors()
+        .take_while(|directory| directory.symlink_metadata().is_err())
+        .collect::<Vec<_>>();
+    for directory in missing.into_iter().rev() {
+        match fs::create_dir(directory) {
+            Ok(()) => policy.attributes.apply_to_dir(directory)?,
+            // Another upload may have created it in the meantime.
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && directory.is_dir() => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    // Check the path again, in case it was changed while it was created.
+    let resolved =
+        resolve_file_path(&policy.file, &policy.directory, policy.symlinks).ok_or_else(denied)?;
+    if !resolved.parent().is_some_and(Path::is_dir) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "parent is not a directory",
+        ));
+    }
+
+    Ok(resolved)
+}
+
+/// Resolves the symbolic links in `file`, which must be below `directory`,
+/// and returns the path it refers to if `symlinks` allows following them.
+/// Dangling symbolic links are never followed, so no file is created through
+/// them. Components after the first one that does not exist are kept as
+/// they are.
+fn resolve_file_path(file: &Path, directory: &Path, symlinks: SymlinkPolicy) -> Option<PathBuf> {
+    let relative = file.strip_prefix(directory).ok()?;
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let root = directory.canonicalize().ok()?;
+    let mut resolved = root.clone();
+    let mut components = relative.components();
+    while let Some(component) = components.next() {
+        let path = resolved.join(component);
+        match path.symlink_metadata() {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                if symlinks == SymlinkPolicy::Deny {
+                    return None;
+                }
+                resolved = path.canonicalize().ok()?;
+                if symlinks == SymlinkPolicy::Inside && !resolved.starts_with(&root) {
+                    return None;
+                }
+            }
+            Ok(_) => resolved = path,
+            Err(_) => {
+                resolved = path;
+                resolved.extend(components);
+                break;
+            }
+        }
+    }
+
+    Some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileFilter, RateLimit};
+    use std::net::Ipv4Addr;
+    use std::{env, fs};
+
+    #[test]
+    fn validates_file_path() {
+        let root = temp_dir("resolve");
+        fs::create_dir_all(root.join("dir/test")).unwrap();
+        let directory = root.join("dir/test");
+        let canonical = directory.canonicalize().unwrap();
+
+        // Paths that are not inside of an existing directory are refused.
+        for (file, directory) in [
+            ("/dir/test/file", "/dir/test"),
+            ("/system/data.txt", "/dir/test"),
+            ("~/some_data.txt", "/dir/test"),
+        ] {
+            assert!(
+                resolve_file_path(Path::new(file), Path::new(directory), SymlinkPolicy::Any)
+                    .is_none(),
+                "{file} in {directory}"
+            );
+        }
+
+        assert_eq!(
+            resolve_file_path(
+                &directory.join("new/file"),
+                &directory,
+                SymlinkPolicy::Inside
+            ),
+            Some(canonical.join("new/file"))
+        );
+        assert_eq!(
+            resolve_file_path(&directory.join("../file"), &directory, SymlinkPolicy::Any),
+            None
+        );
+        assert_eq!(
+            resolve_file_path(&root.join("dir/file"), &directory, SymlinkPolicy::Any),
+            None
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_by_policy() {
+        use std::os::unix::fs::symlink;
+
+        let root = temp_dir("symlinks");
+        let directory = root.join("served");
+        fs::create_dir_all(directory.join("images")).unwrap();
+        fs::write(directory.join("images/kernel"), "kernel").unwrap();
+        fs::write(root.join("secret"), "secret").unwrap();
+        symlink(directory.join("images"), directory.join("current")).unwrap();
+        symlink(root.join("secret"), directory.join("escape")).unwrap();
+        symlink(root.join("missing"), directory.join("dangling")).unwrap();
+        let canonical = directory.canonicalize().unwrap();
+
+        let cases = [
+            (
+                "current/kernel",
+                SymlinkPolicy::Deny,
+                ErrorCode::AccessViolation,
+            ),
+            (
+                "current/kernel",
+                SymlinkPolicy::Inside,
+                ErrorCode::FileExists,
+            ),
+            ("current/kernel", SymlinkPolicy::Any, ErrorCode::FileExists),
+            ("escape", SymlinkPolicy::Deny, ErrorCode::AccessViolation),
+            ("escape", SymlinkPolicy::Inside, ErrorCode::AccessViolation),
+            ("escape", SymlinkPolicy::Any, ErrorCode::FileExists),
+            (
+                "dangling",
+                SymlinkPolicy::Inside,
+                ErrorCode::AccessViolation,
+            ),
+            ("dangling", SymlinkPolicy::Any, ErrorCode::AccessViolation),
+            ("images/kernel", SymlinkPolicy::Deny, ErrorCode::FileExists),
+            ("images/new", SymlinkPolicy::Deny, ErrorCode::FileNotFound),
+        ];
+        for (file, symlinks, expected) in cases {
+            let config = Config {
+                directory: directory.clone(),
+                symlinks,
+                ..Config::default()
+            };
+            assert_eq!(
+                check_read(&config, file),
+                expected,
+                "{file} with {symlinks}"
+            );
+        }
+        assert_eq!(
+            resolve_file_path(
+                &directory.join("current/new"),
+                &directory,
+                SymlinkPolicy::Inside
+            ),
+            Some(canonical.join("images/new"))
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ignores_symlinks_swapped_in_after_check() {
+        let root = temp_dir("symlink-swap");
+        let directory = root.join("served");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("kernel"), "kernel").unwrap();
+        fs::write(root.join("secret"), "secret").unwrap();
+
+        let config = Config {
+            directory: directory.clone(),
+            symlinks: SymlinkPolicy::Deny,
+            ..Config::default()
+        };
+        let policy = Policy::new(&config, 0).unwrap();
+        let request = policy
+            .for_request(0, &"127.0.0.1".parse().unwrap(), "kernel")
+            .unwrap();
+        let (status, file) = check_file_exists(&request, &policy.read_files);
+        assert_eq!(status, ErrorCode::FileExists);
+        assert_eq!(file, directory.canonicalize().unwrap().join("kernel"));
+        assert_eq!(read_first_block(&file, 512).unwrap(), b"kernel");
+
+        fs::remove_file(directory.join("kernel")).unwrap();
+        std::os::unix::fs::symlink(root.join("secret"), directory.join("kernel")).unwrap();
+        assert!(read_first_block(&file, 512).is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn denies_files_by_patterns() {
+        let directory = temp_dir("patterns");
+        fs::create_dir_all(directory.join(".git")).unwrap();
+        fs::create_dir_all(directory.join("dumps")).unwrap();
+        for file in [".git/config", ".profile", "server.key", "kernel", "kernel~"] {
+            fs::write(directory.join(file), file).unwrap();
+        }
+        let config = Config {
+            directory: directory.clone(),
+            read_files: FileFilter {
+                deny: [".git/", ".*", "*.key", "*~"].map(String::from).to_vec(),
+                ..Default::default()
+            },
+            write_files: FileFilter {
+                allow: vec!["dumps/*.core".to_string()],
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+
+        let reads = [
+            (".git/config", ErrorCode::AccessViolation),
+            (".profile", ErrorCode::AccessViolation),
+            ("server.key", ErrorCode::AccessViolation),
+            ("missing.key", ErrorCode::AccessViolation),
+            ("kernel~", ErrorCode::AccessViolation),
+            ("kernel", ErrorCode::FileExists),
+            ("missing", ErrorCode::FileNotFound),
+        ];
+        for (file, expected) in reads {
+            assert_eq!(check_read(&config, file), expected, "read {file}");
+        }
+
+        let policy = Policy::new(&config, 0).unwrap();
+        let client = "127.0.0.1".parse().unwrap();
+        let writes = [
+            ("dumps/app.core", ErrorCode::FileNotFound),
+            ("dumps/app.log", ErrorCode::AccessViolation),
+            ("kernel", ErrorCode::AccessViolation),
+        ];
+        for (file, expected) in writes {
+            let request = policy.for_request(0, &client, file).unwrap();
+            assert_eq!(
+                check_file_exists(&request, &policy.write_files).0,
+                expected,
+                "write {file}"
+            );
+        }
+
+        fs::remove_dir_all(directory).unwrap();
     }
 
-    if !file.exists() {
-        return ErrorCode::FileNotFound;
+    #[test]
+    fn replaces_existing_files_by_policy() {
+        let directory = temp_dir("overwrite");
+        fs::write(directory.join("upload"), b"old").unwrap();
+
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(send_write_request(&client, addr), ErrorCode::FileExists);
+
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .overwrite(OverwritePolicy::Always)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .send_to(&request(Opcode::Wrq, "upload"), addr)
+            .unwrap();
+        let (packet, transfer) = Socket::recv_from(&client).unwrap();
+        assert_eq!(packet, Packet::Ack(0));
+
+        Socket::send_to(
+            &client,
+            &Packet::Data {
+                block_num: 1,
+                data: b"new".to_vec(),
+            },
+            &transfer,
+        )
+        .unwrap();
+        assert_eq!(Socket::recv_from(&client).unwrap().0, Packet::Ack(1));
+        assert_eq!(fs::read(directory.join("upload")).unwrap(), b"new");
+
+        fs::remove_dir_all(directory).unwrap();
     }
 
-    ErrorCode::FileExists
-}
+    #[test]
+    fn creates_missing_directories() {
+        let directory = temp_dir("create-dirs");
+        let outside = temp_dir("create-dirs-outside");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&outside, directory.join("outside")).unwrap();
 
-fn validate_file_path(file: &Path, directory: &PathBuf) -> bool {
-    !file.to_str().unwrap().contains("..") && file.ancestors().any(|a| a == directory)
-}
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            send_request(&client, addr, Opcode::Wrq, "dumps/board42/core"),
+            ErrorCode::FileNotFound
+        );
+        assert!(!directory.join("dumps").exists());
+
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .mount(Mount {
+                read_only: true,
+                ..Mount::new("boot", &directory)
+            })
+            .create_dirs(true)
+            .dir_mode(0o750)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        for file in ["boot/new/kernel", "outside/new/core", "../new/core"] {
+            assert_eq!(
+                send_request(&client, addr, Opcode::Wrq, file),
+                ErrorCode::AccessViolation,
+                "{file}"
+            );
+        }
+        assert!(!directory.join("new").exists());
+        assert!(!outside.join("new").exists());
+
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .send_to(&request(Opcode::Wrq, "dumps/board42/2026-10-16/core"), addr)
+            .unwrap();
+        assert_eq!(Socket::recv_from(&client).unwrap().0, Packet::Ack(0));
+        assert!(directory.join("dumps/board42/2026-10-16").is_dir());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            for created in ["dumps", "dumps/board42", "dumps/board42/2026-10-16"] {
+                let metadata = fs::metadata(directory.join(created)).unwrap();
+                assert_eq!(metadata.permissions().mode() & 0o7777, 0o750, "{created}");
+            }
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+        fs::remove_dir_all(outside).unwrap();
+    }
 
     #[test]
-    fn validates_file_path() {
-        assert!(validate_file_path(
-            &PathBuf::from("/dir/test/file"),
-            &PathBuf::from("/dir/test")
-        ));
+    fn removes_interrupted_uploads_on_request() {
+        let directory = temp_dir("interrupted");
+        let boot = temp_dir("interrupted-boot");
+        let leftover = upload::temp_path(&directory.join("core"));
+        let boot_leftover = upload::temp_path(&boot.join("kernel"));
+        fs::write(&leftover, "partial").unwrap();
+        fs::write(&boot_leftover, "partial").unwrap();
 
-        assert!(!validate_file_path(
-            &PathBuf::from("/system/data.txt"),
-            &PathBuf::from("/dir/test")
-        ));
+        let server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .mount(Mount {
+                read_only: true,
+                ..Mount::new("boot", &boot)
+            })
+            .build()
+            .unwrap();
+        assert!(leftover.exists());
 
-        assert!(!validate_file_path(
-            &PathBuf::from("~/some_data.txt"),
-            &PathBuf::from("/dir/test")
-        ));
+        server.remove_interrupted_uploads();
+        assert!(!leftover.exists());
+        assert!(boot_leftover.exists());
+
+        fs::remove_dir_all(directory).unwrap();
+        fs::remove_dir_all(boot).unwrap();
+    }
+
+    #[test]
+    fn stops_uploads_over_quota() {
+        let directory = temp_dir("quota");
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .quotas(Quotas {
+                max_file_size: Some(600),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let stats = server.stats();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .send_to(&request(Opcode::Wrq, "upload"), addr)
+            .unwrap();
+        let (packet, transfer) = Socket::recv_from(&client).unwrap();
+        assert_eq!(packet, Packet::Ack(0));
 
-        assert!(!validate_file_path(
-            &PathBuf::from("/dir/test/../file"),
-            &PathBuf::from("/dir/test")
+        for (block_num, len) in [(1, 512), (2, 100)] {
+            Socket::send_to(
+                &client,
+                &Packet::Data {
+                    block_num,
+                    data: vec![0; len],
+                },
+                &transfer,
+            )
+            .unwrap();
+        }
+        assert_eq!(Socket::recv_from(&client).unwrap().0, Packet::Ack(1));
+        assert!(matches!(
+            Socket::recv_from(&client).unwrap().0,
+            Packet::Error {
+                code: ErrorCode::DiskFull,
+                ..
+            }
         ));
+        assert_eq!(stats.quota_exceeded(), 1);
+        assert_eq!(stats.uploaded(), 512);
+
+        // The partial file is removed right after the error is sent.
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sets_attributes_of_uploads() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let directory = temp_dir("attributes");
+        let group = fs::metadata(&directory).unwrap().gid();
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .file_mode(0o600)
+            .file_group(&group.to_string())
+            .mount(Mount {
+                file_mode: Some(0o640),
+                ..Mount::new("shared", &directory)
+            })
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        for (file, mode) in [("private", 0o600), ("shared/public", 0o640)] {
+            client.send_to(&request(Opcode::Wrq, file), addr).unwrap();
+            let (packet, transfer) = Socket::recv_from(&client).unwrap();
+            assert_eq!(packet, Packet::Ack(0));
+            Socket::send_to(
+                &client,
+                &Packet::Data {
+                    block_num: 1,
+                    data: b"log".to_vec(),
+                },
+                &transfer,
+            )
+            .unwrap();
+            assert_eq!(Socket::recv_from(&client).unwrap().0, Packet::Ack(1));
+
+            let name = file.rsplit('/').next().unwrap();
+            let metadata = fs::metadata(directory.join(name)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o7777, mode, "{file}");
+            assert_eq!(metadata.gid(), group, "{file}");
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn builder_fails_on_unknown_user() {
+        assert!(ServerBuilder::new()
+            .port(0)
+            .directory(".")
+            .user("no-such-tftpd-user")
+            .build()
+            .is_err());
     }
 
     #[test]
@@ -420,7 +1914,13 @@ mod tests {
 
         let work_type = RequestType::Read(12341234);
 
-        let worker_options = parse_options(&mut options, work_type).unwrap();
+        let worker_options = parse_options(
+            &mut options,
+            work_type,
+            &OptionLimits::default(),
+            &RetryPolicy::default(),
+        )
+        .unwrap();
 
         assert_eq!(options[0].value, worker_options.block_size);
         assert_eq!(options[1].value, worker_options.transfer_size as usize);
@@ -446,7 +1946,13 @@ mod tests {
 
         let work_type = RequestType::Write;
 
-        let worker_options = parse_options(&mut options, work_type).unwrap();
+        let worker_options = parse_options(
+            &mut options,
+            work_type,
+            &OptionLimits::default(),
+            &RetryPolicy::default(),
+        )
+        .unwrap();
 
         assert_eq!(options[0].value, worker_options.block_size);
         assert_eq!(options[1].value, worker_options.transfer_size as usize);
@@ -456,13 +1962,761 @@ mod tests {
     #[test]
     fn parses_default_options() {
         assert_eq!(
-            parse_options(&mut [], RequestType::Write).unwrap(),
+            parse_options(
+                &mut [],
+                RequestType::Write,
+                &OptionLimits::default(),
+                &RetryPolicy::default()
+            )
+            .unwrap(),
             WorkerOptions {
                 block_size: DEFAULT_BLOCK_SIZE,
                 transfer_size: 0,
-                timeout: DEFAULT_TIMEOUT,
+                timeout: RetryPolicy::default().timeout,
                 window_size: DEFAULT_WINDOW_SIZE,
             }
         );
     }
+    #[test]
+    fn limits_requested_options() {
+        let mut options = vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 8192,
+            },
+            TransferOption {
+                option: OptionType::Windowsize,
+                value: 64,
+            },
+        ];
+        let limits = OptionLimits {
+            max_block_size: 1468,
+            max_window_size: 16,
+        };
+
+        let worker_options = parse_options(
+            &mut options,
+            RequestType::Write,
+            &limits,
+            &RetryPolicy::default(),
+        )
+        .unwrap();
+
+        assert_eq!(worker_options.block_size, 1468);
+        assert_eq!(worker_options.window_size, 16);
+        assert_eq!(options[0].value, 1468);
+        assert_eq!(options[1].value, 16);
+    }
+
+    #[test]
+    fn builds_server_on_os_assigned_port() {
+        let server = ServerBuilder::new()
+            .ip_address(Ipv4Addr::LOCALHOST)
+            .port(0)
+            .directory(".")
+            .build()
+            .unwrap();
+
+        let addr = server.local_addr().unwrap();
+        assert_eq!(addr.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_ne!(addr.port(), 0);
+    }
+
+    #[test]
+    fn builder_rejects_invalid_settings() {
+        assert!(ServerBuilder::new()
+            .port(0)
+            .directory("/this/does/not/exist")
+            .build()
+            .is_err());
+
+        assert!(ServerBuilder::new()
+            .port(0)
+            .directory(".")
+            .max_block_size(4)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_requests_with_policies_and_hooks() {
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(".")
+            .read_only(true)
+            .request_hook(|packet, _| match packet {
+                Packet::Rrq { filename, .. } if filename == "secret" => Err(ErrorCode::NoSuchUser),
+                _ => Ok(()),
+            })
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.connect(addr).unwrap();
+
+        client.send(&request(Opcode::Wrq, "upload")).unwrap();
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Error {
+                code: ErrorCode::AccessViolation,
+                ..
+            }
+        ));
+
+        client.send(&request(Opcode::Rrq, "secret")).unwrap();
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Error {
+                code: ErrorCode::NoSuchUser,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn serves_ipv6_clients() {
+        let mut server = ServerBuilder::new()
+            .ip_address(Ipv6Addr::LOCALHOST)
+            .port(0)
+            .directory(".")
+            .read_only(true)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("[::1]:0").unwrap();
+        assert_eq!(
+            send_write_request(&client, addr),
+            ErrorCode::AccessViolation
+        );
+    }
+
+    #[test]
+    fn serves_ipv4_clients_in_dual_stack_mode() {
+        let mut server = ServerBuilder::new()
+            .dual_stack(true)
+            .port(0)
+            .directory(".")
+            .read_only(true)
+            .build()
+            .unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        assert_eq!(
+            send_write_request(&client, addr),
+            ErrorCode::AccessViolation
+        );
+
+        let client = UdpSocket::bind("[::1]:0").unwrap();
+        let addr = SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+        assert_eq!(
+            send_write_request(&client, addr),
+            ErrorCode::AccessViolation
+        );
+    }
+
+    #[test]
+    fn serves_multiple_listeners() {
+        let boot = temp_dir("listeners-boot");
+        let upload = temp_dir("listeners-upload");
+        fs::write(boot.join("kernel"), b"kernel image").unwrap();
+
+        let localhost = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let mut server = ServerBuilder::new()
+            .directory(&upload)
+            .listener(Listener {
+                address: localhost,
+                directory: Some(boot.clone()),
+                read_only: Some(true),
+                write_only: None,
+            })
+            .listener(Listener::new(localhost))
+            .build()
+            .unwrap();
+        let addrs = server.local_addrs().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        client
+            .send_to(&request(Opcode::Rrq, "kernel"), addrs[0])
+            .unwrap();
+        assert_eq!(
+            Socket::recv_from(&client).unwrap().0,
+            Packet::Data {
+                block_num: 1,
+                data: b"kernel image".to_vec()
+            }
+        );
+
+        // The transfer above keeps sending to the first client, so the other
+        // requests are sent from a new one.
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(
+            send_write_request(&client, addrs[0]),
+            ErrorCode::AccessViolation
+        );
+
+        client
+            .send_to(&request(Opcode::Rrq, "kernel"), addrs[1])
+            .unwrap();
+        assert!(matches!(
+            Socket::recv_from(&client).unwrap().0,
+            Packet::Error {
+                code: ErrorCode::FileNotFound,
+                ..
+            }
+        ));
+
+        fs::remove_dir_all(boot).unwrap();
+        fs::remove_dir_all(upload).unwrap();
+    }
+
+    #[test]
+    fn replies_from_request_destination_on_wildcard_listener() {
+        let directory = temp_dir("wildcard");
+        fs::write(directory.join("kernel"), b"kernel image").unwrap();
+
+        let mut server = ServerBuilder::new()
+            .ip_address(Ipv4Addr::UNSPECIFIED)
+            .port(0)
+            .directory(&directory)
+            .build()
+            .unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || server.listen());
+
+        // The whole 127.0.0.0/8 block is routed to the loopback interface, so
+        // requests to 127.0.0.2 must be answered from 127.0.0.2.
+        let destination = Ipv4Addr::new(127, 0, 0, 2);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .send_to(
+                &request(Opcode::Rrq, "kernel"),
+                SocketAddr::from((destination, port)),
+            )
+            .unwrap();
+
+        let (packet, from) = Socket::recv_from(&client).unwrap();
+        assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+        if cfg!(any(target_os = "linux", target_os = "android")) {
+            assert_eq!(from.ip(), destination);
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn binds_transfer_sockets_in_port_range() {
+        let directory = temp_dir("port-range");
+        fs::write(directory.join("kernel"), b"kernel image").unwrap();
+
+        let occupied = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = occupied.local_addr().unwrap().port();
+
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .port_range(port..=port)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .send_to(&request(Opcode::Rrq, "kernel"), addr)
+            .unwrap();
+        assert!(matches!(
+            Socket::recv_from(&client).unwrap(),
+            (
+                Packet::Error {
+                    code: ErrorCode::NotDefined,
+                    ..
+                },
+                from
+            ) if from == addr
+        ));
+
+        drop(occupied);
+        client
+            .send_to(&request(Opcode::Rrq, "kernel"), addr)
+            .unwrap();
+        let (packet, from) = Socket::recv_from(&client).unwrap();
+        assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+        assert_eq!(from.port(), port);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn answers_duplicate_requests_from_the_running_session() {
+        let directory = temp_dir("duplicates");
+        fs::write(directory.join("kernel"), vec![0x55; 4096]).unwrap();
+
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        client
+            .send_to(&request(Opcode::Rrq, "kernel"), addr)
+            .unwrap();
+        client
+            .send_to(&request(Opcode::Rrq, "kernel"), addr)
+            .unwrap();
+
+        let mut sources = Vec::new();
+        while let Ok((packet, from)) = Socket::recv_from(&client) {
+            assert!(matches!(packet, Packet::Data { block_num: 1, .. }));
+            sources.push(from);
+        }
+        sources.dedup();
+        assert_eq!(sources.len(), 1);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn answers_duplicate_requests_with_options_with_oack() {
+        let directory = temp_dir("duplicates-oack");
+        fs::write(directory.join("kernel"), vec![0x55; 4096]).unwrap();
+
+        let mut server = ServerBuilder::new()
+            .port(0)
+            .directory(&directory)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let request = [&request(Opcode::Rrq, "kernel")[..], b"blksize\x001024\x00"].concat();
+        client.send_to(&request, addr).unwrap();
+
+        // OACK packets are only ever sent by the server, so they are told
+        // apart by their opcode.
+        let mut buf = [0; 1100];
+        let (_, transfer) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(buf[..2], Opcode::Oack.as_bytes());
+        Socket::send_to(&client, &Packet::Ack(0), &transfer).unwrap();
+        let (_, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(buf[..4], [0x00, 0x03, 0x00, 0x01]);
+        assert_eq!(from, transfer);
+
+        client.send_to(&request, addr).unwrap();
+        let mut resent = false;
+        while let Ok((_, from)) = client.recv_from(&mut buf) {
+            assert_eq!(from, transfer);
+            match buf[..4] {
+                [0x00, 0x06, ..] => resent = true,
+                [0x00, 0x03, 0x00, 0x01] => {}
+                _ => panic!("unexpected response {:?}", &buf[..4]),
+            }
+        }
+        assert!(resent);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn listens_on_supplied_sockets_until_idle() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let mut server = ServerBuilder::new()
+            .directory(".")
+            .read_only(true)
+            .socket(socket)
+            .idle_timeout(Duration::from_millis(500))
+            .build()
+            .unwrap();
+        assert_eq!(server.local_addrs().unwrap(), vec![addr]);
+        let listener = thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            send_write_request(&client, addr),
+            ErrorCode::AccessViolation
+        );
+
+        thread::sleep(Duration::from_secs(2));
+        assert!(listener.is_finished());
+    }
+
+    #[test]
+    fn accepts_only_uploads_in_write_only_mode() {
+        let directory = temp_dir("write-only");
+        fs::write(directory.join("upload"), b"already uploaded").unwrap();
+
+        let mut server = ServerBuilder::new()
+            .ip_address(Ipv4Addr::LOCALHOST)
+            .port(0)
+            .directory(&directory)
+            .write_only(true)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            send_request(&client, addr, Opcode::Rrq, "upload"),
+            ErrorCode::AccessViolation
+        );
+        assert_eq!(send_write_request(&client, addr), ErrorCode::FileExists);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn denies_clients_by_access_lists() {
+        let directory = temp_dir("access");
+        fs::write(directory.join("file"), b"file").unwrap();
+
+        let mut server = ServerBuilder::new()
+            .ip_address(Ipv4Addr::LOCALHOST)
+            .port(0)
+            .directory(&directory)
+            .read_access(AccessList {
+                deny: vec!["127.0.0.0/8".parse().unwrap()],
+                ..Default::default()
+            })
+            .write_access(AccessList {
+                allow: vec!["10.0.0.0/8".parse().unwrap()],
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            send_request(&client, addr, Opcode::Rrq, "file"),
+            ErrorCode::AccessViolation
+        );
+        assert_eq!(
+            send_request(&client, addr, Opcode::Wrq, "upload"),
+            ErrorCode::AccessViolation
+        );
+        assert!(!directory.join("upload").exists());
+
+        let mut server = ServerBuilder::new()
+            .ip_address(Ipv4Addr::LOCALHOST)
+            .port(0)
+            .directory(&directory)
+            .read_access(AccessList {
+                deny: vec!["127.0.0.1".parse().unwrap()],
+                ..Default::default()
+            })
+            .drop_denied(true)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        client
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        client.send_to(&request(Opcode::Rrq, "file"), addr).unwrap();
+        assert!(Socket::recv_from(&client).is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn drops_requests_over_the_rate_limit() {
+        let mut server = ServerBuilder::new()
+            .ip_address(Ipv4Addr::LOCALHOST)
+            .port(0)
+            .directory(".")
+            .rate_limits(RateLimits {
+                client: Some(RateLimit { rate: 1, burst: 1 }),
+                ban_after: Some(1),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let stats = server.stats();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            send_request(&client, addr, Opcode::Rrq, "missing"),
+            ErrorCode::FileNotFound
+        );
+
+        client
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        for _ in 0..2 {
+            client
+                .send_to(&request(Opcode::Rrq, "missing"), addr)
+                .unwrap();
+        }
+        assert!(Socket::recv_from(&client).is_err());
+
+        assert_eq!(stats.requests(), 3);
+        assert_eq!(stats.rate_limited(), 1);
+        assert_eq!(stats.bans(), 1);
+        assert_eq!(stats.banned(), 1);
+    }
+
+    #[test]
+    fn serves_mounts_with_their_permissions() {
+        let boot = temp_dir("mounts-boot");
+        let dumps = temp_dir("mounts-dumps");
+        fs::write(boot.join("kernel"), b"kernel image").unwrap();
+        fs::write(dumps.join("core"), b"secret").unwrap();
+
+        let mut server = ServerBuilder::new()
+            .ip_address(Ipv4Addr::LOCALHOST)
+            .port(0)
+            .directory(".")
+            .mount(Mount {
+                read_only: true,
+                ..Mount::new("boot/", &boot)
+            })
+            .mount(Mount {
+                write_only: true,
+                ..Mount::new("dumps/", &dumps)
+            })
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            send_request(&client, addr, Opcode::Wrq, "boot/kernel2"),
+            ErrorCode::AccessViolation
+        );
+        assert_eq!(
+            send_request(&client, addr, Opcode::Rrq, "dumps/core"),
+            ErrorCode::AccessViolation
+        );
+        assert_eq!(
+            send_request(&client, addr, Opcode::Wrq, "dumps/core"),
+            ErrorCode::FileExists
+        );
+        assert_eq!(
+            send_request(&client, addr, Opcode::Rrq, "boot/missing"),
+            ErrorCode::FileNotFound
+        );
+
+        client
+            .send_to(&request(Opcode::Rrq, "boot/kernel"), addr)
+            .unwrap();
+        assert_eq!(
+            Socket::recv_from(&client).unwrap().0,
+            Packet::Data {
+                block_num: 1,
+                data: b"kernel image".to_vec()
+            }
+        );
+
+        fs::remove_dir_all(boot).unwrap();
+        fs::remove_dir_all(dumps).unwrap();
+    }
+
+    #[test]
+    fn applies_profile_of_client_network() {
+        let directory = temp_dir("profiles");
+        fs::write(directory.join("upload"), b"already uploaded").unwrap();
+
+        let mut server = ServerBuilder::new()
+            .ip_address(Ipv4Addr::UNSPECIFIED)
+            .port(0)
+            .directory(".")
+            .read_only(true)
+            .profile(Profile {
+                directory: Some(directory.clone()),
+                read_only: Some(false),
+                ..Profile::new(vec!["127.0.0.2/32".parse().unwrap()])
+            })
+            .build()
+            .unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        assert_eq!(
+            send_write_request(&client, addr),
+            ErrorCode::AccessViolation
+        );
+
+        let client = UdpSocket::bind("127.0.0.2:0").unwrap();
+        assert_eq!(send_write_request(&client, addr), ErrorCode::FileExists);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reloads_config_on_sighup() {
+        let directory = temp_dir("reload");
+        fs::write(directory.join("upload"), b"already uploaded").unwrap();
+
+        let config = Config {
+            ip_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 0,
+            read_only: true,
+            ..Config::default()
+        };
+        let reloaded = Arc::new(std::sync::Mutex::new(config.clone()));
+        let source = reloaded.clone();
+
+        let mut server = ServerBuilder::from_config(config)
+            .config_source(move || Ok(source.lock().unwrap().clone()))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.listen());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let reload = |config: &dyn Fn(&mut Config)| {
+            config(&mut reloaded.lock().unwrap());
+            // SAFETY: the handler installed by the server only sets a flag.
+            unsafe { libc::raise(libc::SIGHUP) };
+            thread::sleep(Duration::from_secs(1));
+        };
+
+        reload(&|config| {
+            config.read_only = false;
+            config.directory = PathBuf::from("/this/does/not/exist");
+        });
+        assert_eq!(
+            send_write_request(&client, addr),
+            ErrorCode::AccessViolation
+        );
+
+        reload(&|config| {
+            config.directory = directory.clone();
+            config.single_port = true;
+        });
+        assert_eq!(
+            send_write_request(&client, addr),
+            ErrorCode::AccessViolation
+        );
+
+        reload(&|config| config.single_port = false);
+        assert_eq!(send_write_request(&client, addr), ErrorCode::FileExists);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn reloads_chrooted_config() {
+        let directory = temp_dir("reload-chroot");
+        let config = Config {
+            port: 0,
+            directory: directory.clone(),
+            ..Config::default()
+        };
+        let mut server = Server::new(&config).unwrap();
+        // The config keeps naming the directories outside of the chroot,
+        // while the policy names them inside of it.
+        server.config.chroot = true;
+
+        let (_, policy) = server
+            .reloaded_policy(Config {
+                chroot: true,
+                mounts: vec![Mount::new("boot", &directory)],
+                ..config.clone()
+            })
+            .unwrap();
+        assert_eq!(policy.listeners[0].directory, PathBuf::from("/"));
+        assert_eq!(policy.mounts[0].mount.directory, PathBuf::from("/"));
+
+        assert!(server
+            .reloaded_policy(Config {
+                chroot: true,
+                mounts: vec![Mount::new("tmp", env::temp_dir())],
+                ..config
+            })
+            .is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tftpd-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn send_write_request(client: &UdpSocket, addr: SocketAddr) -> ErrorCode {
+        send_request(client, addr, Opcode::Wrq, "upload")
+    }
+
+    fn check_read(config: &Config, filename: &str) -> ErrorCode {
+        let policy = Policy::new(config, 0).unwrap();
+        let request = policy
+            .for_request(0, &"127.0.0.1".parse().unwrap(), filename)
+            .unwrap();
+
+        check_file_exists(&request, &policy.read_files).0
+    }
+
+    fn send_request(
+        client: &UdpSocket,
+        addr: SocketAddr,
+        opcode: Opcode,
+        filename: &str,
+    ) -> ErrorCode {
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.send_to(&request(opcode, filename), addr).unwrap();
+
+        match Socket::recv_from(client).unwrap() {
+            (Packet::Error { code, .. }, from) if from == addr => code,
+            (packet, _) => panic!("unexpected response {packet:?}"),
+        }
+    }
+
+    fn request(opcode: Opcode, filename: &str) -> Vec<u8> {
+        [
+            &opcode.as_bytes()[..],
+            filename.as_bytes(),
+            &[0x00],
+            b"octet",
+            &[0x00],
+        ]
+        .concat()
+    }
 }
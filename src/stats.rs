@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Stats `struct` counts the requests a [`Server`](crate::Server) has
+/// handled. It is shared with the server, so a copy taken before
+/// [`Server::listen()`](crate::Server::listen) keeps counting while the
+/// server runs.
+///
+/// # Example
+///
+/// ```rust
+/// use std::thread;
+/// use tftpd::ServerBuilder;
+///
+/// let mut server = ServerBuilder::new().port(0).build().unwrap();
+/// let stats = server.stats();
+/// thread::spawn(move || server.listen());
+///
+/// assert_eq!(stats.requests(), 0);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: AtomicU64,
+    rate_limited: AtomicU64,
+    banned: AtomicU64,
+    bans: AtomicU64,
+    uploaded: AtomicU64,
+    quota_exceeded: AtomicU64,
+    daily: Mutex<DailyUsage>,
+}
+
+/// Bytes uploaded by each client since midnight UTC.
+#[derive(Debug, Default)]
+struct DailyUsage {
+    day: u64,
+    clients: HashMap<IpAddr, u64>,
+}
+
+impl Stats {
+    /// Returns the number of read and write requests received.
+    pub fn requests(&self) -> u64 {
+        self.counters.requests.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests dropped for exceeding a rate limit.
+    pub fn rate_limited(&self) -> u64 {
+        self.counters.rate_limited.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests dropped from banned clients.
+    pub fn banned(&self) -> u64 {
+        self.counters.banned.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times a client has been banned.
+    pub fn bans(&self) -> u64 {
+        self.counters.bans.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes received in uploads.
+    pub fn uploaded(&self) -> u64 {
+        self.counters.uploaded.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes `client` has uploaded since midnight UTC.
+    pub fn uploaded_today(&self, client: IpAddr) -> u64 {
+        let daily = self.daily();
+        daily
+            .clients
+            .get(&client.to_canonical())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Returns the number of uploads refused or stopped for exceeding a
+    /// quota or the free space watermark.
+    pub fn quota_exceeded(&self) -> u64 {
+        self.counters.quota_exceeded.load(Ordering::Relaxed)
+    }
+
+    /// Counts `bytes` uploaded by `client`, unless that takes it over
+    /// `daily_limit`, in which case nothing is counted and `false` is
+    /// returned.
+    pub(crate) fn count_upload(
+        &self,
+        client: IpAddr,
+        bytes: u64,
+        daily_limit: Option<u64>,
+    ) -> bool {
+        let mut daily = self.daily();
+        let used = daily.clients.entry(client.to_canonical()).or_default();
+        if daily_limit.is_some_and(|limit| used.saturating_add(bytes) > limit) {
+            return false;
+        }
+        *used += bytes;
+        self.counters.uploaded.fetch_add(bytes, Ordering::Relaxed);
+
+        true
+    }
+
+    pub(crate) fn count_quota_exceeded(&self) {
+        self.counters.quota_exceeded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Locks the daily usage, starting over when a new day has begun.
+    fn daily(&self) -> MutexGuard<'_, DailyUsage> {
+        let today = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() / SECONDS_PER_DAY)
+            .unwrap_or_default();
+        let mut daily = self
+            .counters
+            .daily
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if daily.day != today {
+            daily.day = today;
+            daily.clients.clear();
+        }
+
+        daily
+    }
+
+    pub(crate) fn count_request(&self) {
+        self.counters.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_rate_limited(&self) {
+        self.counters.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_banned(&self) {
+        self.counters.banned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_ban(&self) {
+        self.counters.bans.fetch_add(1, Ordering::Relaxed);
+    }
+}
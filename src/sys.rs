@@ -0,0 +1,899 @@
+use std::fs::File;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::net::{Ipv4Addr, SocketAddrV6};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+#[cfg(unix)]
+use std::{
+    env,
+    ffi::CString,
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::{
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket},
+};
+
+/// Binds a [`UdpSocket`] to the IPv6 unspecified address `::` with
+/// `IPV6_V6ONLY` turned off, so that it accepts both IPv6 and IPv4-mapped
+/// traffic.
+#[cfg(unix)]
+pub fn bind_dual_stack(port: u16) -> io::Result<UdpSocket> {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    // SAFETY: the descriptor is checked and immediately owned by the
+    // `UdpSocket`, which closes it on every return path.
+    let socket = unsafe {
+        let fd = libc::socket(libc::AF_INET6, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        UdpSocket::from_raw_fd(fd)
+    };
+
+    let off: libc::c_int = 0;
+    // SAFETY: `off` outlives the call and its size is passed along.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_V6ONLY,
+            &off as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: an all-zero `sockaddr_in6` is the unspecified address.
+    let mut addr: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+    addr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+    addr.sin6_port = port.to_be();
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))]
+    {
+        addr.sin6_len = std::mem::size_of::<libc::sockaddr_in6>() as u8;
+    }
+
+    // SAFETY: `addr` is a valid `sockaddr_in6` and its size is passed along.
+    let result = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(socket)
+}
+
+/// Binds a [`UdpSocket`] to the IPv6 unspecified address `::`. Platforms
+/// without `IPV6_V6ONLY` control keep their default behaviour.
+#[cfg(not(unix))]
+pub fn bind_dual_stack(port: u16) -> io::Result<UdpSocket> {
+    UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))
+}
+
+/// Binds a [`UdpSocket`] to the supplied address, turning `IPV6_V6ONLY` off
+/// for the IPv6 unspecified address when `dual_stack` is set.
+pub fn bind(addr: SocketAddr, dual_stack: bool) -> io::Result<UdpSocket> {
+    if dual_stack && addr.ip() == Ipv6Addr::UNSPECIFIED {
+        bind_dual_stack(addr.port())
+    } else {
+        UdpSocket::bind(addr)
+    }
+}
+
+/// Asks the kernel to report the destination address of every datagram
+/// received on the socket, see [`recv_from_with_destination()`].
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn enable_packet_info(socket: &UdpSocket) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let on: libc::c_int = 1;
+    let set_option = |level, name| {
+        // SAFETY: `on` outlives the call and its size is passed along.
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &on as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+
+    if socket.local_addr()?.is_ipv4() {
+        set_option(libc::IPPROTO_IP, libc::IP_PKTINFO)
+    } else {
+        set_option(libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO)
+    }
+}
+
+/// Reports that destination addresses are not available on this platform.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn enable_packet_info(_socket: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "packet info is not supported on this platform",
+    ))
+}
+
+/// Receives a datagram like [`UdpSocket::recv_from()`], additionally
+/// returning the local address to answer it from if it has been enabled with
+/// [`enable_packet_info()`]. This is the address the datagram was sent to,
+/// except for broadcasts and multicasts, which are answered from the address
+/// the kernel would reply from.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn recv_from_with_destination(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: all-zero values are valid for these plain C structures.
+    let mut source: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut control = [0u64; 16];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut source as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    // SAFETY: every pointer in `msg` points to a live buffer of the given size.
+    let amt = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if amt < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut destination = None;
+    // SAFETY: the control messages were filled in by the kernel and are
+    // walked with the libc macros within `msg_controllen`.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                    let info = std::ptr::read_unaligned(data as *const libc::in_pktinfo);
+                    let addr = Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr));
+                    destination = Some(IpAddr::V4(
+                        match addr.is_broadcast() || addr.is_multicast() {
+                            true => Ipv4Addr::from(u32::from_be(info.ipi_spec_dst.s_addr)),
+                            false => addr,
+                        },
+                    ));
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                    let info = std::ptr::read_unaligned(data as *const libc::in6_pktinfo);
+                    destination = Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)));
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok((
+        amt as usize,
+        socket_addr(&source)?,
+        destination.and_then(reply_source),
+    ))
+}
+
+/// Returns `destination` if replies can be sent from it, or `None` if the
+/// address of the listener has to be used instead.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reply_source(destination: IpAddr) -> Option<IpAddr> {
+    let usable = match destination {
+        IpAddr::V4(addr) => !(addr.is_broadcast() || addr.is_multicast()),
+        IpAddr::V6(addr) => !addr.is_multicast(),
+    };
+
+    (usable && !destination.is_unspecified()).then_some(destination)
+}
+
+/// Receives a datagram like [`UdpSocket::recv_from()`]. Destination
+/// addresses are not available on this platform.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn recv_from_with_destination(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+    let (amt, addr) = socket.recv_from(buf)?;
+
+    Ok((amt, addr, None))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn socket_addr(storage: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            // SAFETY: the family says the storage holds a `sockaddr_in`.
+            let addr = unsafe { *(storage as *const _ as *const libc::sockaddr_in) };
+            Ok(SocketAddr::from((
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: the family says the storage holds a `sockaddr_in6`.
+            let addr = unsafe { *(storage as *const _ as *const libc::sockaddr_in6) };
+            Ok(SocketAddr::from(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unknown address family",
+        )),
+    }
+}
+
+/// Takes over the UDP sockets passed by a service manager using the systemd
+/// socket activation protocol (`LISTEN_PID` and `LISTEN_FDS`). The variables
+/// are left for the binary to clear, since changing the environment is not
+/// safe while other threads may read it.
+#[cfg(unix)]
+pub fn listen_fds() -> io::Result<Vec<UdpSocket>> {
+    const SD_LISTEN_FDS_START: libc::c_int = 3;
+
+    listen_fds_from(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        SD_LISTEN_FDS_START,
+    )
+}
+
+/// Takes over the sockets passed by socket activation according to the
+/// values of `LISTEN_PID` and `LISTEN_FDS`, starting at the descriptor
+/// `start`.
+#[cfg(unix)]
+fn listen_fds_from(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    start: libc::c_int,
+) -> io::Result<Vec<UdpSocket>> {
+    let count = listen_fds_count(listen_pid, listen_fds, process::id())?;
+
+    (start..start + count as libc::c_int)
+        .map(inherited_socket)
+        .collect()
+}
+
+/// Reports that socket activation is not available on this platform.
+#[cfg(not(unix))]
+pub fn listen_fds() -> io::Result<Vec<UdpSocket>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "socket activation is not supported on this platform",
+    ))
+}
+
+/// Returns the number of sockets passed to the process `pid` according to the
+/// values of `LISTEN_PID` and `LISTEN_FDS`.
+#[cfg(unix)]
+fn listen_fds_count(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    pid: u32,
+) -> io::Result<usize> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+
+    match listen_pid.map(str::parse::<u32>) {
+        Some(Ok(listen_pid)) if listen_pid == pid => {}
+        Some(Ok(_)) => return Err(invalid("LISTEN_PID does not match this process")),
+        Some(Err(_)) => return Err(invalid("LISTEN_PID is not a process id")),
+        None => return Err(invalid("LISTEN_PID is not set")),
+    }
+
+    match listen_fds.map(str::parse::<usize>) {
+        Some(Ok(0)) | None => Err(invalid("no sockets were passed in LISTEN_FDS")),
+        Some(Ok(count)) => Ok(count),
+        Some(Err(_)) => Err(invalid("LISTEN_FDS is not a number")),
+    }
+}
+
+/// Takes ownership of an inherited file descriptor, making sure that it is a
+/// datagram socket.
+#[cfg(unix)]
+pub fn inherited_socket(fd: libc::c_int) -> io::Result<UdpSocket> {
+    use std::os::unix::io::FromRawFd;
+
+    check_datagram_socket(fd)?;
+
+    // SAFETY: the descriptor was checked to be an open datagram socket, and it
+    // was handed over to this process to own.
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    // SAFETY: setting the close-on-exec flag on a descriptor we own.
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+
+    Ok(socket)
+}
+
+/// Takes over the datagram socket inetd passes as the standard input in
+/// `wait` mode. Standard output and standard error refer to the same socket,
+/// so they are redirected to `/dev/null` to keep log messages from being sent
+/// to clients.
+#[cfg(unix)]
+pub fn stdin_socket() -> io::Result<UdpSocket> {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    check_datagram_socket(libc::STDIN_FILENO)?;
+
+    // SAFETY: duplicating an open descriptor, the copy is owned by the socket.
+    let socket = unsafe {
+        let fd = libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 3);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        UdpSocket::from_raw_fd(fd)
+    };
+
+    let null = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    let socket_id = file_id(libc::STDIN_FILENO)?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if fd == libc::STDIN_FILENO || file_id(fd).ok() == Some(socket_id) {
+            // SAFETY: replacing a standard descriptor with an open one.
+            if unsafe { libc::dup2(null.as_raw_fd(), fd) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+
+    Ok(socket)
+}
+
+/// Reports that inetd is not available on this platform.
+#[cfg(not(unix))]
+pub fn stdin_socket() -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "inetd mode is not supported on this platform",
+    ))
+}
+
+#[cfg(unix)]
+fn check_datagram_socket(fd: libc::c_int) -> io::Result<()> {
+    let mut socket_type: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `socket_type` and `len` outlive the call and describe a valid buffer.
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut socket_type as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if socket_type != libc::SOCK_DGRAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("file descriptor {fd} is not a datagram socket"),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_id(fd: libc::c_int) -> io::Result<(libc::dev_t, libc::ino_t)> {
+    // SAFETY: an all-zero `stat` is valid, and it is filled in by `fstat`.
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((stat.st_dev, stat.st_ino))
+}
+
+/// Looks up a user by name or numeric id, returning its user id and primary
+/// group id.
+#[cfg(unix)]
+pub fn lookup_user(user: &str) -> io::Result<(u32, u32)> {
+    // SAFETY: an all-zero `passwd` is valid, and it is only read after a
+    // successful lookup filled it in using `buf`, which outlives it.
+    unsafe {
+        let mut passwd: libc::passwd = std::mem::zeroed();
+        let mut buf = vec![0 as libc::c_char; 16384];
+        let mut result = std::ptr::null_mut();
+
+        let error = match user.parse::<libc::uid_t>() {
+            Ok(uid) => libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result),
+            Err(_) => {
+                let name = CString::new(user)?;
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            }
+        };
+        if error != 0 {
+            return Err(io::Error::from_raw_os_error(error));
+        }
+        if result.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("user {user} does not exist"),
+            ));
+        }
+
+        Ok((passwd.pw_uid, passwd.pw_gid))
+    }
+}
+
+/// Looks up a group by name or numeric id, returning its group id.
+#[cfg(unix)]
+pub fn lookup_group(group: &str) -> io::Result<u32> {
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+
+    // SAFETY: an all-zero `group` is valid, and it is only read after a
+    // successful lookup filled it in using `buf`, which outlives it.
+    unsafe {
+        let mut entry: libc::group = std::mem::zeroed();
+        let mut buf = vec![0 as libc::c_char; 16384];
+        let mut result = std::ptr::null_mut();
+        let name = CString::new(group)?;
+
+        let error = libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        );
+        if error != 0 {
+            return Err(io::Error::from_raw_os_error(error));
+        }
+        if result.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("group {group} does not exist"),
+            ));
+        }
+
+        Ok(entry.gr_gid)
+    }
+}
+
+/// The directory the process was confined to by [`chroot()`].
+static ROOT: OnceLock<Root> = OnceLock::new();
+
+/// The root directory of the process, by the names it had before the chroot.
+struct Root {
+    /// The working directory before the chroot, where relative paths start.
+    cwd: PathBuf,
+    /// The directory as it was named, and with symbolic links resolved.
+    names: [PathBuf; 2],
+}
+
+impl Root {
+    fn path(&self, path: &Path) -> Option<PathBuf> {
+        let path = self.cwd.join(path);
+        self.names
+            .iter()
+            .find_map(|name| path.strip_prefix(name).ok())
+            .map(|relative| Path::new("/").join(relative))
+    }
+}
+
+/// Returns where `path`, named as before the root directory of the process
+/// was changed, is found now, or `None` if it is outside of the new root.
+/// Without a chroot, the path is returned unchanged.
+pub fn path_in_root(path: &Path) -> Option<PathBuf> {
+    match ROOT.get() {
+        Some(root) => root.path(path),
+        None => Some(path.to_path_buf()),
+    }
+}
+
+/// Changes the root directory of the process to `directory`, and the working
+/// directory to the new root.
+#[cfg(unix)]
+pub fn chroot(directory: &Path) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let cwd = env::current_dir()?;
+    let root = directory.canonicalize()?;
+    let path = CString::new(root.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a valid C string that outlives the call.
+    if unsafe { libc::chroot(path.as_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let _ = ROOT.set(Root {
+        names: [cwd.join(directory), root],
+        cwd,
+    });
+
+    env::set_current_dir("/")
+}
+
+/// Permanently switches the process to the supplied group and user ids,
+/// dropping all supplementary groups.
+#[cfg(unix)]
+pub fn drop_privileges(uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    // SAFETY: plain system calls without pointers, except for `setgroups`
+    // which is passed a valid one-element array.
+    unsafe {
+        if let Some(gid) = gid {
+            if libc::geteuid() == 0 && libc::setgroups(1, &gid) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::setgid(gid) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(uid) = uid {
+            if libc::setuid(uid) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Make sure root privileges cannot be regained.
+            if uid != 0 && libc::setuid(0) == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "root privileges could be regained after dropping them",
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the user running the process can search `directory`, and
+/// read and write it if requested.
+#[cfg(unix)]
+pub fn check_access(directory: &Path, read: bool, write: bool) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut mode = libc::X_OK;
+    if read {
+        mode |= libc::R_OK;
+    }
+    if write {
+        mode |= libc::W_OK;
+    }
+
+    let path = CString::new(directory.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a valid C string that outlives the call.
+    if unsafe { libc::access(path.as_ptr(), mode) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_access(directory: &Path, _read: bool, write: bool) -> io::Result<()> {
+    if write && directory.metadata()?.permissions().readonly() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "directory is read-only",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Sets the permission bits of `path` to `mode`, regardless of the umask.
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+/// Sets the permission bits of an open `file` to `mode`.
+#[cfg(unix)]
+pub fn set_file_mode(file: &File, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    file.set_permissions(std::fs::Permissions::from_mode(mode))
+}
+
+/// Changes the owner and group of `path`, without following a symbolic
+/// link.
+#[cfg(unix)]
+pub fn chown(path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    std::os::unix::fs::lchown(path, uid, gid)
+}
+
+/// Changes the owner and group of an open `file`.
+#[cfg(unix)]
+pub fn fchown(file: &File, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    std::os::unix::fs::fchown(file, uid, gid)
+}
+
+/// Opens `path` for reading, failing if it is a symbolic link.
+#[cfg(unix)]
+pub fn open_no_follow(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    File::options()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+pub fn open_no_follow(path: &Path) -> io::Result<File> {
+    if path.symlink_metadata()?.file_type().is_symlink() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "file is a symbolic link",
+        ));
+    }
+
+    File::open(path)
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_file_mode(_file: &File, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn chown(_path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+    Err(unsupported_privileges())
+}
+
+#[cfg(not(unix))]
+pub fn fchown(_file: &File, _uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+    Err(unsupported_privileges())
+}
+
+/// Returns the number of bytes available to unprivileged users on the file
+/// system containing `path`.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a valid C string that outlives the call, and `stat`
+    // is only read after `statvfs` has filled it in.
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The field types differ between platforms.
+        #[allow(clippy::unnecessary_cast)]
+        let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+        Ok(free)
+    }
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space can only be checked on unix",
+    ))
+}
+
+/// Set by the SIGHUP handler, and cleared by [`take_sighup()`].
+#[cfg(unix)]
+static SIGHUP_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a SIGHUP handler that records the signal instead of terminating
+/// the process. See [`take_sighup()`].
+#[cfg(unix)]
+pub fn catch_sighup() -> io::Result<()> {
+    extern "C" fn handle_sighup(_signal: libc::c_int) {
+        SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    // SAFETY: `action` is fully initialized before being passed to
+    // `sigaction`, and the handler only stores to an atomic.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGHUP, &action, std::ptr::null_mut()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether a SIGHUP was received since the last call.
+#[cfg(unix)]
+pub fn take_sighup() -> bool {
+    SIGHUP_RECEIVED.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(not(unix))]
+pub fn catch_sighup() -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn take_sighup() -> bool {
+    false
+}
+
+#[cfg(not(unix))]
+pub fn lookup_user(_user: &str) -> io::Result<(u32, u32)> {
+    Err(unsupported_privileges())
+}
+
+#[cfg(not(unix))]
+pub fn lookup_group(_group: &str) -> io::Result<u32> {
+    Err(unsupported_privileges())
+}
+
+#[cfg(not(unix))]
+pub fn chroot(_directory: &Path) -> io::Result<()> {
+    Err(unsupported_privileges())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(_uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+    Err(unsupported_privileges())
+}
+
+#[cfg(not(unix))]
+fn unsupported_privileges() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "changing users and root directories is not supported on this platform",
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::io::IntoRawFd;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn replies_from_unicast_addresses_only() {
+        let cases = [
+            ("192.168.1.10", true),
+            ("::1", true),
+            ("255.255.255.255", false),
+            ("224.0.0.1", false),
+            ("ff02::1", false),
+            ("0.0.0.0", false),
+        ];
+        for (destination, usable) in cases {
+            let destination = destination.parse().unwrap();
+            assert_eq!(
+                reply_source(destination),
+                usable.then_some(destination),
+                "{destination}"
+            );
+        }
+    }
+
+    #[test]
+    fn counts_listen_fds() {
+        assert_eq!(listen_fds_count(Some("42"), Some("2"), 42).unwrap(), 2);
+
+        assert!(listen_fds_count(None, Some("2"), 42).is_err());
+        assert!(listen_fds_count(Some("41"), Some("2"), 42).is_err());
+        assert!(listen_fds_count(Some("42"), Some("0"), 42).is_err());
+        assert!(listen_fds_count(Some("42"), Some("two"), 42).is_err());
+    }
+
+    #[test]
+    fn takes_over_activated_sockets() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        // The passed socket is moved to a high descriptor, as the low ones
+        // belong to the test harness.
+        let fd = socket.into_raw_fd();
+        // SAFETY: duplicating an open descriptor, the copy is owned below and
+        // the original is closed.
+        let start = unsafe {
+            let start = libc::fcntl(fd, libc::F_DUPFD, 500);
+            libc::close(fd);
+            start
+        };
+        assert!(start >= 500);
+
+        let pid = process::id().to_string();
+        assert!(listen_fds_from(Some("1"), Some("1"), start).is_err());
+        let sockets = listen_fds_from(Some(&pid), Some("1"), start).unwrap();
+
+        assert_eq!(sockets.len(), 1);
+        assert_eq!(sockets[0].local_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn finds_paths_inside_chroot() {
+        let root = Root {
+            cwd: PathBuf::from("/srv"),
+            names: [PathBuf::from("/srv/tftp"), PathBuf::from("/data/tftp")],
+        };
+
+        let cases = [
+            ("/srv/tftp", Some("/")),
+            ("/srv/tftp/boot", Some("/boot")),
+            ("/data/tftp/tftpd.toml", Some("/tftpd.toml")),
+            ("tftp/boot", Some("/boot")),
+            ("/etc/tftpd.toml", None),
+            ("/srv/tftpd", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(
+                root.path(Path::new(path)),
+                expected.map(PathBuf::from),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn looks_up_users_and_groups() {
+        assert_eq!(lookup_user("root").unwrap(), (0, 0));
+        assert_eq!(lookup_user("0").unwrap(), (0, 0));
+        assert_eq!(lookup_group("0").unwrap(), 0);
+
+        assert!(lookup_user("no-such-tftpd-user").is_err());
+        assert!(lookup_group("no-such-tftpd-group").is_err());
+    }
+
+    #[test]
+    fn takes_over_inherited_sockets() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let socket = inherited_socket(socket.into_raw_fd()).unwrap();
+        assert_eq!(socket.local_addr().unwrap(), addr);
+
+        let stream = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = stream.into_raw_fd();
+        assert!(inherited_socket(fd).is_err());
+        // SAFETY: the descriptor was not taken over, so it is closed here.
+        unsafe { libc::close(fd) };
+    }
+}
@@ -0,0 +1,550 @@
+use std::error::Error;
+use std::fmt::{self, Write};
+
+/// A value of a configuration file. Only the subset of TOML needed for
+/// configuration is supported: strings, integers, booleans and arrays of
+/// those, which may span lines.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+/// A `key = value` line of a configuration file.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Entry {
+    pub(crate) key: String,
+    pub(crate) value: Value,
+    pub(crate) line: usize,
+}
+
+/// The entries below a `[name]` or `[[name]]` header, or the entries before
+/// the first header when `name` is empty.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Table {
+    pub(crate) name: String,
+    pub(crate) array: bool,
+    pub(crate) line: usize,
+    pub(crate) entries: Vec<Entry>,
+}
+
+impl Entry {
+    /// Creates an [`Entry`] that is not part of a file, for writing one.
+    pub(crate) fn new<V: Into<Value>>(key: &str, value: V) -> Entry {
+        Entry {
+            key: key.to_string(),
+            value: value.into(),
+            line: 0,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Result<&str, Box<dyn Error>> {
+        match &self.value {
+            Value::String(value) => Ok(value),
+            _ => Err(self.type_error("a string")),
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Result<bool, Box<dyn Error>> {
+        match self.value {
+            Value::Boolean(value) => Ok(value),
+            _ => Err(self.type_error("a boolean")),
+        }
+    }
+
+    pub(crate) fn as_integer<T: TryFrom<i64>>(&self) -> Result<T, Box<dyn Error>> {
+        match self.value {
+            Value::Integer(value) => T::try_from(value).map_err(|_| {
+                format!(
+                    "line {}: {} is out of range for {}",
+                    self.line, value, self.key
+                )
+                .into()
+            }),
+            _ => Err(self.type_error("an integer")),
+        }
+    }
+
+    pub(crate) fn as_str_array(&self) -> Result<Vec<&str>, Box<dyn Error>> {
+        match &self.value {
+            Value::Array(values) => values
+                .iter()
+                .map(|value| match value {
+                    Value::String(value) => Ok(value.as_str()),
+                    _ => Err(self.type_error("an array of strings")),
+                })
+                .collect(),
+            _ => Err(self.type_error("an array of strings")),
+        }
+    }
+
+    /// Returns an error naming the line of the entry.
+    pub(crate) fn error(&self, msg: &str) -> Box<dyn Error> {
+        format!("line {}: {msg}", self.line).into()
+    }
+
+    /// Returns the error for a key that is not known in its table.
+    pub(crate) fn unknown(&self) -> Box<dyn Error> {
+        self.error(&format!("unknown key {}", self.key))
+    }
+
+    fn type_error(&self, expected: &str) -> Box<dyn Error> {
+        self.error(&format!("{} must be {expected}", self.key))
+    }
+}
+
+impl Table {
+    /// Creates an empty [`Table`] that is not part of a file, for writing one.
+    pub(crate) fn new(name: &str, array: bool) -> Table {
+        Table {
+            name: name.to_string(),
+            array,
+            line: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the error for a table that is not known.
+    pub(crate) fn unknown(&self) -> Box<dyn Error> {
+        format!("line {}: unknown table {}", self.line, self.name).into()
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::Array(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(value) => {
+                f.write_char('"')?;
+                for c in value.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        c => f.write_char(c)?,
+                    }
+                }
+                f.write_char('"')
+            }
+            Value::Integer(value) => write!(f, "{value}"),
+            Value::Boolean(value) => write!(f, "{value}"),
+            Value::Array(values) => {
+                f.write_char('[')?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_char(']')
+            }
+        }
+    }
+}
+
+/// Writes tables in the format read by [`parse()`]. Tables without entries
+/// are left out, unless they are array tables.
+pub(crate) fn write(tables: &[Table]) -> String {
+    let mut text = String::new();
+
+    for table in tables {
+        if !table.name.is_empty() {
+            if table.entries.is_empty() && !table.array {
+                continue;
+            }
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            match table.array {
+                true => text.push_str(&format!("[[{}]]\n", table.name)),
+                false => text.push_str(&format!("[{}]\n", table.name)),
+            }
+        }
+        for entry in &table.entries {
+            text.push_str(&format!("{} = {}\n", entry.key, entry.value));
+        }
+    }
+
+    text
+}
+
+/// Parses the text of a configuration file into its tables. The first table
+/// holds the entries before any header, and is always present.
+pub(crate) fn parse(text: &str) -> Result<Vec<Table>, Box<dyn Error>> {
+    let mut tables = vec![Table {
+        name: String::new(),
+        array: false,
+        line: 0,
+        entries: Vec::new(),
+    }];
+
+    let mut lines = text.lines().enumerate();
+    while let Some((index, raw)) = lines.next() {
+        let line = index + 1;
+        let content = strip_comment(raw).trim();
+        if content.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = content.strip_prefix('[') {
+            let (name, array) = match header.strip_prefix('[') {
+                Some(name) => (name.strip_suffix("]]"), true),
+                None => (header.strip_suffix(']'), false),
+            };
+            let name = name
+                .map(str::trim)
+                .filter(|name| is_bare_key(name))
+                .ok_or_else(|| format!("line {line}: invalid table header"))?;
+            if tables
+                .iter()
+                .any(|table| table.name == name && !(array && table.array))
+            {
+                return Err(format!("line {line}: duplicate table {name}").into());
+            }
+
+            tables.push(Table {
+                name: name.to_string(),
+                array,
+                line,
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        let (key, value) = content
+            .split_once('=')
+            .ok_or_else(|| format!("line {line}: expected key = value"))?;
+        let key = key.trim();
+        if !is_bare_key(key) {
+            return Err(format!("line {line}: invalid key {key}").into());
+        }
+        let mut value = value.trim().to_string();
+        // An array continues on the following lines until it is closed.
+        while open_arrays(&value) > 0 {
+            let (_, raw) = lines
+                .next()
+                .ok_or_else(|| format!("line {line}: unterminated array"))?;
+            value.push(' ');
+            value.push_str(strip_comment(raw).trim());
+        }
+        let value = parse_value(&value).map_err(|err| format!("line {line}: {err}"))?;
+
+        let table = tables.last_mut().expect("the root table is always present");
+        if table.entries.iter().any(|entry| entry.key == key) {
+            return Err(format!("line {line}: duplicate key {key}").into());
+        }
+        table.entries.push(Entry {
+            key: key.to_string(),
+            value,
+            line,
+        });
+    }
+
+    Ok(tables)
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Removes a `#` comment from a line, unless the `#` is inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..index],
+            _ => {}
+        }
+        escaped = false;
+    }
+
+    line
+}
+
+/// Returns how many arrays are still open at the end of `value`, ignoring
+/// brackets inside strings.
+fn open_arrays(value: &str) -> usize {
+    let mut depth = 0_usize;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for c in value.chars() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => depth += 1,
+            (None, ']') => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        escaped = false;
+    }
+
+    depth
+}
+
+fn parse_value(value: &str) -> Result<Value, Box<dyn Error>> {
+    let (parsed, rest) = parse_partial(value)?;
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected {} after value", rest.trim()).into());
+    }
+
+    Ok(parsed)
+}
+
+/// Parses the value at the start of `value`, returning it along with the
+/// remaining text.
+fn parse_partial(value: &str) -> Result<(Value, &str), Box<dyn Error>> {
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(string), &rest[index + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((_, '\\')) => string.push('\\'),
+                    Some((_, '"')) => string.push('"'),
+                    _ => return Err("invalid escape sequence in string".into()),
+                },
+                c => string.push(c),
+            }
+        }
+        return Err("unterminated string".into());
+    }
+
+    if let Some(rest) = value.strip_prefix('\'') {
+        let (string, rest) = rest.split_once('\'').ok_or("unterminated string")?;
+        return Ok((Value::String(string.to_string()), rest));
+    }
+
+    if let Some(mut rest) = value.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(rest) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), rest));
+            }
+
+            let (value, remaining) = parse_partial(rest)?;
+            values.push(value);
+            rest = remaining.trim_start();
+            match rest.strip_prefix(',') {
+                Some(remaining) => rest = remaining,
+                None if rest.starts_with(']') => {}
+                None => return Err("expected , or ] in array".into()),
+            }
+        }
+    }
+
+    let end = value
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(value.len());
+    let (token, rest) = value.split_at(end);
+    let parsed = match token {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        token => Value::Integer(
+            token
+                .replace('_', "")
+                .parse()
+                .map_err(|_| format!("invalid value {token}"))?,
+        ),
+    };
+
+    Ok((parsed, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tables_and_values() {
+        let tables = parse(
+            r#"
+# Served files
+directory = "/srv/tftp" # trailing comment
+port = 6_969
+read_only = true
+
+[limits]
+max_block_size = 1468
+
+[[listener]]
+address = '127.0.0.1:69'
+
+[[listener]]
+tags = ["a#b", "c\"d", 'e']
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(tables.len(), 4);
+        assert_eq!(
+            tables[0].entries,
+            vec![
+                Entry {
+                    key: "directory".to_string(),
+                    value: Value::String("/srv/tftp".to_string()),
+                    line: 3,
+                },
+                Entry {
+                    key: "port".to_string(),
+                    value: Value::Integer(6969),
+                    line: 4,
+                },
+                Entry {
+                    key: "read_only".to_string(),
+                    value: Value::Boolean(true),
+                    line: 5,
+                },
+            ]
+        );
+        assert_eq!(tables[1].name, "limits");
+        assert!(!tables[1].array);
+        assert_eq!(tables[2].name, "listener");
+        assert!(tables[3].array);
+        assert_eq!(
+            tables[3].entries[0].value,
+            Value::Array(vec![
+                Value::String("a#b".to_string()),
+                Value::String("c\"d".to_string()),
+                Value::String("e".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_arrays_spanning_lines() {
+        let tables = parse(
+            r#"
+clients = [
+    "10.0.0.0/8", # build farm
+    "192.168.1.0/24",
+]
+tags = [["a]", 1],
+    [true]]
+port = 69
+"#,
+        )
+        .unwrap();
+
+        let entries = &tables[0].entries;
+        assert_eq!(entries[0].line, 2);
+        assert_eq!(
+            entries[0].as_str_array().unwrap(),
+            vec!["10.0.0.0/8", "192.168.1.0/24"]
+        );
+        assert_eq!(
+            entries[1].value,
+            Value::Array(vec![
+                Value::Array(vec![Value::from("a]"), Value::from(1_i64)]),
+                Value::Array(vec![Value::from(true)]),
+            ])
+        );
+        assert_eq!((entries[2].key.as_str(), entries[2].line), ("port", 8));
+
+        let err = parse("clients = [\n\"10.0.0.0/8\",\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: unterminated array");
+    }
+
+    #[test]
+    fn returns_error_naming_the_line() {
+        for (text, line) in [
+            ("port = 69\nport = 70", "line 2"),
+            ("[limits]\n[limits]", "line 2"),
+            ("\n\ndirectory = \"/srv", "line 3"),
+            ("port = 69 70", "line 1"),
+            ("[listener", "line 1"),
+            ("just text", "line 1"),
+            ("a.b = 1", "line 1"),
+        ] {
+            let err = parse(text).unwrap_err().to_string();
+            assert!(err.starts_with(line), "{text:?} returned {err}");
+        }
+    }
+
+    #[test]
+    fn writes_parsable_tables() {
+        let mut root = Table::new("", false);
+        root.entries
+            .push(Entry::new("directory", "C:\\tftp \"root\""));
+        root.entries.push(Entry::new("port", 69_i64));
+        let mut listener = Table::new("listener", true);
+        listener.entries.push(Entry::new(
+            "tags",
+            vec![Value::from("a"), Value::from(true)],
+        ));
+        let tables = vec![root, Table::new("limits", false), listener];
+
+        let text = write(&tables);
+        assert_eq!(
+            text,
+            "directory = \"C:\\\\tftp \\\"root\\\"\"\nport = 69\n\n[[listener]]\ntags = [\"a\", true]\n"
+        );
+
+        let parsed = parse(&text).unwrap();
+        assert_eq!(parsed[0].entries[0].value, tables[0].entries[0].value);
+        assert_eq!(parsed[1].entries[0].value, tables[2].entries[0].value);
+    }
+
+    #[test]
+    fn converts_entry_values() {
+        let tables = parse("port = 70000\nuser = 1").unwrap();
+
+        assert!(tables[0].entries[0].as_integer::<u16>().is_err());
+        assert_eq!(tables[0].entries[0].as_integer::<u32>().unwrap(), 70000);
+        assert_eq!(
+            tables[0].entries[1].as_str().unwrap_err().to_string(),
+            "line 2: user must be a string"
+        );
+    }
+}
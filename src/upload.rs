@@ -0,0 +1,93 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Suffix of the hidden files uploads are written to before they are
+/// renamed into place.
+const TEMP_SUFFIX: &str = ".tftpd-part";
+
+/// How many levels of subdirectories are searched for interrupted uploads.
+const MAX_CLEANUP_DEPTH: usize = 4;
+
+static NEXT_UPLOAD: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a unique hidden path in the directory of `file` for an upload to
+/// be written to, so that readers never see a partially written file.
+pub(crate) fn temp_path(file: &Path) -> PathBuf {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    let upload = NEXT_UPLOAD.fetch_add(1, Ordering::Relaxed);
+
+    file.with_file_name(format!(".{name}.{}-{upload}{TEMP_SUFFIX}", process::id()))
+}
+
+/// Removes the files of uploads that were interrupted by a crash from
+/// `directory` and its subdirectories, up to [`MAX_CLEANUP_DEPTH`] levels
+/// deep. Symbolic links are not followed.
+pub(crate) fn remove_leftovers(directory: &Path) {
+    remove_leftovers_below(directory, MAX_CLEANUP_DEPTH);
+}
+
+fn remove_leftovers_below(directory: &Path, depth: usize) {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Could not clean up {}: {err}", directory.display());
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if file_type.is_dir() {
+            if depth > 0 {
+                remove_leftovers_below(&path, depth - 1);
+            }
+        } else if file_type.is_file() && is_temp(&entry.file_name()) {
+            match fs::remove_file(&path) {
+                Ok(_) => println!("Removed interrupted upload {}", path.display()),
+                Err(err) => eprintln!("Could not remove {}: {err}", path.display()),
+            }
+        }
+    }
+}
+
+fn is_temp(name: &OsStr) -> bool {
+    name.to_str()
+        .is_some_and(|name| name.starts_with('.') && name.ends_with(TEMP_SUFFIX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn removes_interrupted_uploads() {
+        let root = env::temp_dir().join(format!("tftpd-leftovers-{}", process::id()));
+        fs::create_dir_all(root.join("dumps")).unwrap();
+
+        let temp = temp_path(&root.join("dumps/core"));
+        assert_ne!(temp, temp_path(&root.join("dumps/core")));
+        assert_eq!(temp.parent(), Some(root.join("dumps").as_path()));
+        fs::write(&temp, "partial").unwrap();
+        fs::write(root.join("dumps/core"), "complete").unwrap();
+        fs::write(root.join("notes.tftpd-part"), "not hidden").unwrap();
+        let deep = root.join("a/b/c/d/e");
+        fs::create_dir_all(&deep).unwrap();
+        let deep_temp = temp_path(&deep.join("core"));
+        fs::write(&deep_temp, "partial").unwrap();
+
+        remove_leftovers(&root);
+        assert!(!temp.exists());
+        assert!(root.join("dumps/core").exists());
+        assert!(root.join("notes.tftpd-part").exists());
+        assert!(deep_temp.exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -188,6 +188,7 @@ mod tests {
         clean(FILE_NAME);
     }
 
+    #[allow(clippy::collapsible_if)]
     fn initialize(file_name: &str) -> File {
         let file_name = DIR_NAME.to_string() + "/" + file_name;
         if !Path::new(DIR_NAME).is_dir() {
@@ -2,12 +2,17 @@
 // clear && cargo build && sudo cargo run -- -i 0.0.0.0 -p 69 -d "$HOME/tftproot"
 // curl -v --output initrd tftp://192.168.x.x/initrd
 
-use crate::{Packet, Socket, Window};
+use crate::overwrite;
+use crate::policy::Attributes;
+use crate::quota::UploadQuota;
+use crate::{sys, upload};
+use crate::{ErrorCode, OverwritePolicy, Packet, Socket, Window};
 use std::{
     error::Error,
     fs::{self, File},
-    path::PathBuf,
-    thread,
+    io,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
@@ -46,6 +51,11 @@ pub struct Worker<T: Socket + ?Sized> {
     blk_size: usize,
     timeout: Duration,
     windowsize: u16,
+    max_retries: u32,
+    overwrite: OverwritePolicy,
+    quota: Option<UploadQuota>,
+    attributes: Attributes,
+    follow_symlinks: bool,
 }
 
 impl<T: Socket + ?Sized> Worker<T> {
@@ -63,18 +73,61 @@ impl<T: Socket + ?Sized> Worker<T> {
             blk_size,
             timeout,
             windowsize,
+            max_retries: MAX_RETRIES,
+            overwrite: OverwritePolicy::Never,
+            quota: None,
+            attributes: Attributes::default(),
+            follow_symlinks: true,
         }
     }
 
+    /// Sets how many times the [`Worker`] waits for the client before
+    /// abandoning the transfer. (default: 6)
+    pub fn max_retries(mut self, max_retries: u32) -> Worker<T> {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets whether a received file may replace a file with the same name
+    /// that already exists once the transfer is complete. (default: never)
+    pub fn overwrite(mut self, overwrite: OverwritePolicy) -> Worker<T> {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Sets the quota a received file is checked against.
+    pub(crate) fn quota(mut self, quota: UploadQuota) -> Worker<T> {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Sets the modes and owner a received file is given.
+    pub(crate) fn attributes(mut self, attributes: Attributes) -> Worker<T> {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Sets whether a file that is sent may be a symbolic link, which is not
+    /// the case for paths whose links were already resolved.
+    pub(crate) fn follow_symlinks(mut self, follow_symlinks: bool) -> Worker<T> {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
     /// Sends a file to the remote [`SocketAddr`] that has sent a read request using
-    /// a random port, asynchronously.
-    pub fn send(self) -> Result<(), Box<dyn Error>> {
+    /// a random port, asynchronously. The returned [`JoinHandle`] can be used to
+    /// find out when the transfer has finished.
+    pub fn send(self) -> Result<JoinHandle<()>, Box<dyn Error>> {
         let file_name = self.file_name.clone();
         let remote_addr = self.socket.remote_addr().unwrap();
 
-        thread::spawn(move || {
+        let handle = thread::spawn(move || {
             let handle_send = || -> Result<(), Box<dyn Error>> {
-                self.send_file(File::open(&file_name)?)?;
+                let file = match self.follow_symlinks {
+                    true => File::open(&file_name)?,
+                    false => sys::open_no_follow(&file_name)?,
+                };
+                self.send_file(file)?;
 
                 Ok(())
             };
@@ -93,18 +146,48 @@ impl<T: Socket + ?Sized> Worker<T> {
             }
         });
 
-        Ok(())
+        Ok(handle)
     }
 
     /// Receives a file from the remote [`SocketAddr`] that has sent a write request using
-    /// the supplied socket, asynchronously.
-    pub fn receive(self) -> Result<(), Box<dyn Error>> {
+    /// the supplied socket, asynchronously. The returned [`JoinHandle`] can be used
+    /// to find out when the transfer has finished.
+    ///
+    /// The file is written to a hidden temporary file in the same directory,
+    /// which is only renamed to the requested name once the whole file has
+    /// been received and synced to disk.
+    pub fn receive(self) -> Result<JoinHandle<()>, Box<dyn Error>> {
         let file_name = self.file_name.clone();
+        let temp_name = upload::temp_path(&file_name);
         let remote_addr = self.socket.remote_addr().unwrap();
 
-        thread::spawn(move || {
+        let handle = thread::spawn(move || {
             let handle_receive = || -> Result<(), Box<dyn Error>> {
-                self.receive_file(File::create(&file_name)?)?;
+                // Never follow a symbolic link created since the request was checked.
+                let file = File::options()
+                    .write(true)
+                    .create_new(true)
+                    .open(&temp_name)?;
+                let block_number = self.receive_file(file.try_clone()?)?;
+
+                let committed = self
+                    .attributes
+                    .apply_to_file(&file)
+                    .and_then(|_| commit(&file, &temp_name, &file_name, self.overwrite));
+                if let Err(err) = committed {
+                    let code = match err.kind() {
+                        io::ErrorKind::AlreadyExists => ErrorCode::FileExists,
+                        _ => ErrorCode::AccessViolation,
+                    };
+                    self.socket.send(&Packet::Error {
+                        code,
+                        msg: "could not store file".to_string(),
+                    })?;
+                    return Err(
+                        format!("Could not store {}: {err}", file_name.to_string_lossy()).into(),
+                    );
+                }
+                self.socket.send(&Packet::Ack(block_number))?;
 
                 Ok(())
             };
@@ -119,14 +202,14 @@ impl<T: Socket + ?Sized> Worker<T> {
                 }
                 Err(err) => {
                     eprintln!("{err}");
-                    if fs::remove_file(&file_name).is_err() {
-                        eprintln!("Error while cleaning {}", &file_name.to_str().unwrap());
+                    if temp_name.exists() && fs::remove_file(&temp_name).is_err() {
+                        eprintln!("Error while cleaning {}", &temp_name.to_str().unwrap());
                     }
                 }
             }
         });
 
-        Ok(())
+        Ok(handle)
     }
 
     fn send_file(self, file: File) -> Result<(), Box<dyn Error>> {
@@ -159,10 +242,12 @@ impl<T: Socket + ?Sized> Worker<T> {
                     }
                     _ => {
                         retry_cnt += 1;
-                        if retry_cnt == MAX_RETRIES {
-                            return Err(
-                                format!("Transfer timed out after {MAX_RETRIES} tries").into()
-                            );
+                        if retry_cnt >= self.max_retries {
+                            return Err(format!(
+                                "Transfer timed out after {} tries",
+                                self.max_retries
+                            )
+                            .into());
                         }
                     }
                 }
@@ -176,8 +261,11 @@ impl<T: Socket + ?Sized> Worker<T> {
         Ok(())
     }
 
-    fn receive_file(self, file: File) -> Result<(), Box<dyn Error>> {
+    /// Receives blocks until the last one and returns its block number,
+    /// which is left for the caller to acknowledge.
+    fn receive_file(&self, file: File) -> Result<u16, Box<dyn Error>> {
         let mut block_number: u16 = 0;
+        let mut received: u64 = 0;
         let mut window = Window::new(self.windowsize, self.blk_size, file);
 
         loop {
@@ -191,6 +279,16 @@ impl<T: Socket + ?Sized> Worker<T> {
                         data,
                     }) => {
                         if received_block_number == block_number.wrapping_add(1) {
+                            if let Some(quota) = &self.quota {
+                                if let Err(msg) = quota.add(received, data.len() as u64) {
+                                    self.socket.send(&Packet::Error {
+                                        code: ErrorCode::DiskFull,
+                                        msg: msg.to_string(),
+                                    })?;
+                                    return Err(format!("Upload stopped: {msg}").into());
+                                }
+                            }
+                            received += data.len() as u64;
                             block_number = received_block_number;
                             size = data.len();
                             window.add(data)?;
@@ -209,26 +307,47 @@ impl<T: Socket + ?Sized> Worker<T> {
                     }
                     _ => {
                         retry_cnt += 1;
-                        if retry_cnt == MAX_RETRIES {
-                            return Err(
-                                format!("Transfer timed out after {MAX_RETRIES} tries").into()
-                            );
+                        if retry_cnt >= self.max_retries {
+                            return Err(format!(
+                                "Transfer timed out after {} tries",
+                                self.max_retries
+                            )
+                            .into());
                         }
                     }
                 }
             }
 
             window.empty()?;
-            self.socket.send(&Packet::Ack(block_number))?;
             if size < self.blk_size {
                 break;
             };
+            self.socket.send(&Packet::Ack(block_number))?;
         }
 
-        Ok(())
+        Ok(block_number)
     }
 }
 
+/// Syncs the received `file` to disk and moves it from `temp_name` to
+/// `file_name` according to the `overwrite` policy.
+fn commit(
+    file: &File,
+    temp_name: &Path,
+    file_name: &Path,
+    overwrite: OverwritePolicy,
+) -> io::Result<()> {
+    file.sync_all()?;
+    overwrite::replace(temp_name, file_name, overwrite)?;
+
+    // Make the rename durable as well, where directories can be synced.
+    if let Some(Ok(directory)) = file_name.parent().map(File::open) {
+        let _ = directory.sync_all();
+    }
+
+    Ok(())
+}
+
 fn send_window<T: Socket>(
     socket: &T,
     window: &Window,